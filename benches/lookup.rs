@@ -0,0 +1,64 @@
+//! Nearest tile lookup vs exhaustive search: `cargo bench --bench lookup`.
+//! That both agree is checked by `tests/lookup.rs`
+use std::time::{Duration, Instant};
+use image::Rgb;
+use factorio_printer::printer::{ColorMetric, Tileset};
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+use common::{synthetic_tileset, XorShift};
+
+const SAMPLES: usize = 1 << 20;
+
+/// Pixels of a smooth noisy gradient, like a downscaled photo
+fn image_samples(rng: &mut XorShift) -> Vec<Rgb<u8>> {
+    (0..SAMPLES).map(|i| {
+        let (x, y) = ((i % 1024) as u32, (i / 1024) as u32);
+        let noise = rng.next() % 16;
+        Rgb::from([
+            ((x / 4 + noise) % 256) as u8,
+            ((y / 4 + noise) % 256) as u8,
+            (((x + y) / 8 + noise) % 256) as u8,
+        ])
+    }).collect()
+}
+
+fn time<F: FnMut(&Rgb<u8>) -> Option<usize>>(samples: &[Rgb<u8>], lookup: F) -> Duration {
+    let start = Instant::now();
+    std::hint::black_box(samples.iter().map(lookup).collect::<Vec<_>>());
+    start.elapsed()
+}
+
+fn main() {
+    let mut rng = XorShift(0x1234_5678);
+    let samples = image_samples(&mut rng);
+    let tilesets = [
+        ("colorcoding", Tileset::preset_color_coding().unwrap()),
+        ("synthetic-256", synthetic_tileset(256, &mut rng)),
+    ];
+    let metrics = [
+        ("rgb", ColorMetric::default()),
+        ("redmean", ColorMetric::Redmean),
+        ("cie76", ColorMetric::Cie76),
+        ("ciede2000", ColorMetric::Ciede2000),
+        ("oklab", ColorMetric::Oklab),
+    ];
+    println!("{} pixels per run", samples.len());
+    println!("{:<15} {:<10} {:>12} {:>12} {:>8}", "tileset", "metric", "exhaustive", "lookup", "speedup");
+    for (tileset_name, tileset) in tilesets {
+        let mut tileset = tileset;
+        for (metric_name, metric) in metrics {
+            tileset.set_metric(metric);
+            let slow = time(&samples, |c| tileset.nearest_exhaustive(c));
+            let fast = time(&samples, |c| tileset.nearest(c));
+            println!(
+                "{:<15} {:<10} {:>10.1}ms {:>10.1}ms {:>7.1}x",
+                tileset_name,
+                metric_name,
+                slow.as_secs_f64() * 1000.0,
+                fast.as_secs_f64() * 1000.0,
+                slow.as_secs_f64() / fast.as_secs_f64()
+            );
+        }
+    }
+}
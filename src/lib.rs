@@ -0,0 +1,34 @@
+//! Turns images into Factorio blueprints made of tiles and 1x1 entities, and renders blueprints back.
+//!
+//! Everything lives in [`printer`]. Printing an image goes like this:
+//!
+//! ```no_run
+//! use factorio_printer::printer::{load_image, FactorioBPStringBuilder, ImagePrinter, TargetSize, Tileset};
+//!
+//! fn main() -> factorio_printer::PrinterResult<()> {
+//!     // tileset: a preset, a file read with `Tileset::read_as` or one built with `TilesetBuilder`
+//!     let tileset = Tileset::preset_color_coding()?;
+//!     // load image
+//!     let (image, _) = load_image(&std::fs::read("picture.png")?)?;
+//!     // preprocess and quantize: resize, blend over background, dither into tileset entries
+//!     let (_, indexed) = ImagePrinter::new(&tileset)
+//!         .size(TargetSize::Width(200))
+//!         .print(image)?;
+//!     // build blueprint, or a book of blueprints with `split`, and serialize it
+//!     let blueprint = FactorioBPStringBuilder::new("picture", &indexed, &tileset)
+//!         .split(100)
+//!         .factorio_serialize()?;
+//!     println!("{}", blueprint);
+//!     Ok(())
+//! }
+//! ```
+//!
+//! The `cli` feature, on by default, builds the `factorio-printer` binary and pulls in `clap`.
+//! Depend on the crate with `default-features = false` to get only the library.
+
+pub mod printer;
+
+pub use printer::PrinterError;
+
+/// Result of everything that can fail in this crate
+pub type PrinterResult<T> = Result<T, PrinterError>;
@@ -1,51 +1,73 @@
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
-mod printer;
+const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-use clap::{Arg, ArgMatches, Command, value_parser};
+mod serve;
+
+use clap::{Arg, ArgAction, ArgMatches, Command, value_parser};
+use clap::parser::ValueSource;
 use std::io::{Read, Write, stdin, stdout, Cursor};
-use image::{Rgba, RgbaImage};
-use image::imageops::colorops::dither;
+use image::{ImageFormat, Rgb, RgbaImage};
+use std::error::Error;
 use std::fs::File;
 use std::path::Path;
-use image::imageops::{CatmullRom, resize};
-use crate::printer::{FactorioBPStringBuilder, Tileset};
-
-type PrinterResult<T> = Result<T, Box<dyn std::error::Error>>;
+use factorio_printer::{printer, PrinterError, PrinterResult};
+use factorio_printer::printer::{BlueprintRenderer, CalibrationPattern, ColorMetric, ColorView, DitherMode, FactorioBPStringBuilder, GroundColor, GroundSprites, ImagePrinter, IndexedImage, NameFilter, PresetSource, Presets, Problem, PrototypeNames, ResizeFilter, Severity, TargetSize, TieBreak, TileKind, TileNames, TileOrder, Tileset, TilesetCheck, TilesetFormat, TilesetPreview};
 
-fn extract_alpha(image: &RgbaImage) -> RgbaImage {
-    let (dim_x, dim_y) = image.dimensions();
-    let mut alpha_layer = RgbaImage::from_pixel(
-        dim_x,
-        dim_y,
-        Rgba::from([0; 4])
-    );
-    for (x,y,pix) in image.enumerate_pixels() {
-        alpha_layer.put_pixel(x, y, Rgba::from([0, 0, 0, pix.0[3]]));
+/// Parses `WxH` box size
+fn parse_size(value: &str) -> PrinterResult<(u32, u32)> {
+    match value.split_once(['x', 'X']) {
+        Some((w, h)) => {
+            let parse = |side: &str| side.trim().parse::<u32>()
+                .map_err(|e| PrinterError::InvalidInput(format!("bad size '{}': {}", value, e)));
+            let (w, h) = (parse(w)?, parse(h)?);
+            if w == 0 || h == 0 {
+                return Err(PrinterError::InvalidInput(format!("size must not be zero, got '{}'", value)));
+            }
+            Ok((w, h))
+        }
+        None => Err(PrinterError::InvalidInput(format!("expected size as WxH, got '{}'", value))),
     }
-    alpha_layer
 }
 
-fn apply_alpha(image: &mut RgbaImage, alpha: &RgbaImage) {
-    for (pix, alpha) in image.pixels_mut().zip(alpha.pixels()) {
-        pix.0[3] = alpha.0[3];
+fn get_target_size(args: &ArgMatches) -> PrinterResult<TargetSize> {
+    if let Some(fit) = args.get_one::<String>("fit") {
+        let (w, h) = parse_size(fit)?;
+        return Ok(TargetSize::Fit(w, h));
+    }
+    if let Some(width) = args.get_one::<u32>("width") {
+        return Ok(TargetSize::Width(*width));
+    }
+    if let Some(height) = args.get_one::<u32>("height") {
+        return Ok(TargetSize::Height(*height));
     }
+    let scale = *args.get_one::<f32>("scale").expect("default scale value");
+    Ok(TargetSize::Scale(scale))
 }
 
-fn scale_image(mut image: RgbaImage, scale: f32) -> RgbaImage {
-    if scale != 1.0f32 {
-        let (dim_x, dim_y) = image.dimensions();
-        let scaled_x = dim_x as f32 * scale;
-        let scaled_y = dim_y as f32 * scale;
-        image = resize(&mut image, scaled_x.round() as u32, scaled_y.round() as u32, CatmullRom);
+fn parse_values<T: std::str::FromStr, const N: usize>(value: &str) -> PrinterResult<[T; N]>
+where T::Err: std::fmt::Display {
+    let parts = value.split(',')
+        .map(|part| part.trim().parse::<T>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| PrinterError::InvalidInput(format!("bad value in '{}': {}", value, e)))?;
+    match <[T; N]>::try_from(parts) {
+        Ok(values) => Ok(values),
+        Err(_) => Err(PrinterError::InvalidInput(format!("expected {} comma separated values, got '{}'", N, value))),
     }
-    image
+}
+
+fn parse_rgb_weights(value: &str) -> PrinterResult<[f32; 3]> {
+    parse_values(value)
+}
+
+fn parse_rgb(value: &str) -> PrinterResult<Rgb<u8>> {
+    Ok(Rgb::from(parse_values::<u8, 3>(value)?))
 }
 
 fn get_input_from_path(path: &str) -> PrinterResult<Box<dyn Read>> {
     if path == "-" {
         Ok(Box::new(stdin()))
     } else {
-        let file = File::open(path)?;
+        let file = File::open(path).map_err(|e| PrinterError::io(format!("can't open {}", path), e))?;
         Ok(Box::new(file))
     }
 }
@@ -56,195 +78,823 @@ fn get_output_from_path(path: &str) -> PrinterResult<Option<Box<dyn Write>>> {
     } else if path == "!" {
         Ok(None)
     } else {
-        let file = File::create(path)?;
+        let file = File::create(path).map_err(|e| PrinterError::io(format!("can't create {}", path), e))?;
         Ok(Some(Box::new(file)))
     }
 }
 
-fn process_image(
+/// Output of an optional file argument, `None` when it's not given or disabled
+fn get_optional_output(args: &ArgMatches, id: &str) -> PrinterResult<Option<Box<dyn Write>>> {
+    match args.get_one::<String>(id) {
+        Some(path) => get_output_from_path(path),
+        None => Ok(None),
+    }
+}
+
+fn write_image(mut writer: Box<dyn Write>, image: &RgbaImage, format: ImageFormat) -> PrinterResult<()> {
+    let mut buf = Cursor::new(vec![]);
+    image.write_to(&mut buf, format)
+        .map_err(|e| PrinterError::image("can't encode output image", e))?;
+    writer.write_all(buf.get_ref())
+        .map_err(|e| PrinterError::io("can't write output image", e))
+}
+
+/// Image printer set up by `print_args`
+fn get_image_printer<'a>(tileset: &'a Tileset, args: &ArgMatches) -> PrinterResult<ImagePrinter<'a>> {
+    let mut print = ImagePrinter::new(tileset)
+        .size(get_target_size(args)?)
+        .filter(args.get_one::<String>("filter")
+            .and_then(|name| ResizeFilter::from_name(name))
+            .expect("filter default value"))
+        .linear_light(args.get_flag("linear_light"))
+        .dither(args.get_one::<String>("dither")
+            .and_then(|name| DitherMode::from_name(name))
+            .expect("dither default value"))
+        .dither_strength(*args.get_one::<f32>("dither_strength").expect("dither strength default value"))
+        .serpentine(args.get_flag("serpentine"))
+        .alpha_threshold(*args.get_one::<u8>("alpha").expect("alpha default value"));
+    if let Some(background) = args.get_one::<String>("background") {
+        print = print.background(parse_rgb(background)?);
+    }
+    Ok(print)
+}
+
+/// Blueprint builder set up by `blueprint_args`
+fn get_blueprint_builder<'a>(
     name: &str,
-    input: Box<dyn Read>,
-    out_img: Option<Box<dyn Write>>,
-    out_bp: Option<Box<dyn Write>>,
-    tileset: &Tileset,
+    indexed: &'a IndexedImage,
+    tileset: &'a Tileset,
     args: &ArgMatches
-) -> PrinterResult<()> {
-    let image_buffer = printer::read_all(input)?;
-    let format = image::guess_format(&image_buffer)?;
-    let mut image = image::load_from_memory_with_format(&image_buffer, format)?.to_rgba8();
-    let scale = *args.get_one::<f32>("scale").expect("default scale value");
-    image = scale_image(image, scale);
-    // alpha channel gets overwritten by dithering, so we save a copy
-    let alpha_layer = extract_alpha(&image);
-    dither(&mut image, tileset);
-
-    match out_bp {
-        Some(mut writer) => {
-            let alpha = *args.get_one::<u8>("alpha").expect("alpha default value");
-            let split = *args.get_one::<i32>("split").expect("split default value");
-            let builder =
-                FactorioBPStringBuilder::new(name, &image, &alpha_layer, tileset)
-                .alpha_threshold(alpha)
-                .split(split);
-            let export_string = match builder.factorio_serialize() {
-                Ok(e) => e,
-                Err(e) => {
-                    eprintln!("error exporting blueprint: {}", e);
-                    std::process::exit(-1)
-                }
-            };
-            writer.write_all(export_string.as_bytes())?;
-        },
-        None => {}
+) -> FactorioBPStringBuilder<'a> {
+    let split = *args.get_one::<i32>("split").expect("split default value");
+    let compression = *args.get_one::<u32>("compression").expect("compression default value");
+    let tile_order = args.get_one::<String>("tile_order")
+        .and_then(|name| TileOrder::from_name(name))
+        .expect("tile order default value");
+    FactorioBPStringBuilder::new(name, indexed, tileset)
+        .compression(compression)
+        .tile_order(tile_order)
+        .split(split)
+}
+
+/// Blueprint label, file name of the image or a generic one for stdin
+fn image_name(path: &str) -> String {
+    let path = Path::new(path);
+    path.file_name()
+        .filter(|_| path.is_file())
+        .and_then(|name| name.to_str())
+        .unwrap_or("Printed image")
+        .to_string()
+}
+
+fn print_image(args: &ArgMatches) -> PrinterResult<()> {
+    let tileset = get_print_tileset(args)?;
+    let path = args.get_one::<String>("image").expect("required");
+    let out_img = get_optional_output(args, "output_image")?;
+    // blueprint goes to stdout unless some output is asked for
+    let out_bp = match args.get_one::<String>("output_blueprint") {
+        Some(path) => get_output_from_path(path)?,
+        None if args.contains_id("output_image") => None,
+        None => get_output_from_path("-")?,
+    };
+
+    let (image, format) = printer::load_image(&printer::read_all(get_input_from_path(path)?)?)?;
+    let (mut image, indexed) = get_image_printer(&tileset, args)?.print(image)?;
+    if let Some(writer) = out_bp {
+        let builder = get_blueprint_builder(&image_name(path), &indexed, &tileset, args);
+        if !builder.has_position_icons() {
+            eprintln!("warning: resulting split side count >99, icons will be set to 0");
+        }
+        builder.factorio_serialize_to(writer)?;
+    }
+    if let Some(writer) = out_img {
+        indexed.paint(&mut image, &tileset);
+        write_image(writer, &image, format)?;
     }
+    Ok(())
+}
 
-    match out_img {
-        Some(mut writer) => {
-            apply_alpha(&mut image, &alpha_layer);
-            let mut buf = Cursor::new(vec![]);
-            image.write_to(&mut buf, format)?;
-            writer.write_all(buf.get_ref())?;
-        },
-        None => {}
+fn read_blueprint_string(path: &str) -> PrinterResult<String> {
+    String::from_utf8(printer::read_all(get_input_from_path(path)?)?).map_err(|e| PrinterError::BlueprintDecode {
+        message: "blueprint string is not valid UTF-8".to_string(),
+        source: Some(e.into()),
+    })
+}
+
+fn decode_blueprint(args: &ArgMatches) -> PrinterResult<()> {
+    let data = read_blueprint_string(args.get_one::<String>("blueprint").expect("required"))?;
+    let blueprint = printer::factorio_deserialize(&data)?;
+    let json = serde_json::to_string_pretty(&blueprint)
+        .map_err(|e| PrinterError::blueprint_decode("can't convert blueprint to JSON", e))?;
+    if let Some(mut writer) = get_output_from_path(args.get_one::<String>("output").expect("output default value"))? {
+        writeln!(writer, "{}", json).map_err(|e| PrinterError::io("can't write blueprint JSON", e))?;
     }
+    Ok(())
+}
 
+fn render_blueprint(args: &ArgMatches) -> PrinterResult<()> {
+    let tileset = get_tileset(args)?;
+    let data = read_blueprint_string(args.get_one::<String>("blueprint").expect("required"))?;
+    let blueprint = printer::factorio_deserialize(&data)?;
+    let (image, warnings) = BlueprintRenderer::new(&blueprint, &tileset).render()?;
+    print_warnings(warnings);
+    if let Some(writer) = get_optional_output(args, "output_image")? {
+        write_image(writer, &image, ImageFormat::Png)?;
+    }
     Ok(())
 }
 
-fn parse_args(args: &ArgMatches) -> PrinterResult<()> {
-    let mut tileset = Tileset::preset_color_coding();
+/// Counts of every tileset entry the image is printed with
+fn image_stats(args: &ArgMatches) -> PrinterResult<()> {
+    let tileset = get_print_tileset(args)?;
+    let path = args.get_one::<String>("image").expect("required");
+    let (image, _) = printer::load_image(&printer::read_all(get_input_from_path(path)?)?)?;
+    let (_, indexed) = get_image_printer(&tileset, args)?.print(image)?;
 
-    match args.get_one::<String>("preset") {
-        Some(preset) => {
-            if preset == "base" {
-                tileset = Tileset::preset_base_game();
+    let mut counts = vec![0usize; tileset.len()];
+    for (_, _, index) in indexed.enumerate() {
+        counts[index] += 1;
+    }
+    let (width, height) = indexed.dimensions();
+    let placed: usize = counts.iter().sum();
+    println!("{}x{}, {} placed, {} skipped", width, height, placed, (width * height) as usize - placed);
+    // most used first, ties keep tileset order
+    let mut used: Vec<_> = tileset.iter().zip(counts).filter(|(_, count)| *count > 0).collect();
+    used.sort_by(|(_, a), (_, b)| b.cmp(a));
+    let mut total_cost = None;
+    for (tile, count) in used {
+        let kind = match tile.kind() {
+            TileKind::Tile => "tile",
+            TileKind::Entity => "entity",
+        };
+        match tile.cost() {
+            Some(cost) => {
+                let cost = cost * count as f32;
+                *total_cost.get_or_insert(0.0) += cost;
+                println!("{}\t{}\t{}\t{}", count, kind, tile.name(), cost);
             }
+            None => println!("{}\t{}\t{}", count, kind, tile.name()),
         }
-        None => {}
     }
+    if let Some(cost) = total_cost {
+        println!("total cost {}", cost);
+    }
+    Ok(())
+}
 
-    match args.get_one::<String>("tileset") {
-        Some(path) => {
-            let input = get_input_from_path(path)?;
-            tileset = Tileset::read(input)?;
-        }
-        None => {}
-    }
-
-    match args.get_one::<String>("input_image") {
-        Some(path) => {
-            let input = get_input_from_path(path)?;
-            let name = if Path::new(path).is_file() {
-                Path::new(path)
-                    .file_name()
-                    .expect("valid filename")
-                    .to_str()
-                    .expect("valid unicode filename")
-                    .to_string()
-            } else {
-                "Printed image".to_string()
-            };
-            let out_img = match args.get_one::<String>("output_image") {
-                Some(path) => {
-                    match get_output_from_path(path)? {
-                        Some(out) => Some(out),
-                        None => None,
-                    }
-                },
-                None => None,
-            };
-            let out_bp = match args.get_one::<String>("output_blueprint") {
-                Some(path) => {
-                    match get_output_from_path(path)? {
-                        Some(out) => Some(out),
-                        None => None,
-                    }
-                },
-                None => None,
-            };
-            process_image(&name, input, out_img, out_bp, &tileset, &args)?;
-        }
-        None => {}
+/// Format and palette names of a tileset file from `--tileset-format` and `--tileset-names`
+fn get_tileset_format(path: &str, args: &ArgMatches) -> PrinterResult<(Option<TilesetFormat>, Option<TileNames>)> {
+    let format = args.get_one::<String>("tileset_format")
+        .and_then(|name| TilesetFormat::from_name(name))
+        .or_else(|| TilesetFormat::from_path(path));
+    let names = match args.get_one::<String>("tileset_names") {
+        Some(names) => Some(TileNames::read(get_input_from_path(names)?)?),
+        None => None,
+    };
+    Ok((format, names))
+}
+
+fn check_tileset(args: &ArgMatches) -> PrinterResult<()> {
+    let path = args.get_one::<String>("tileset").expect("required");
+    let (format, names) = get_tileset_format(path, args)?;
+    let mut tileset = Tileset::read_unchecked(get_input_from_path(path)?, format, names.as_ref())?;
+    tileset.set_view(get_view(args));
+    let known_names = match args.get_one::<String>("dump") {
+        Some(path) => Some(PrototypeNames::from_dump(get_input_from_path(path)?)?),
+        None => None,
+    };
+    let mut check = TilesetCheck::new()
+        .min_distance(*args.get_one::<f32>("min_distance").expect("min distance default value"));
+    if let Some(known_names) = &known_names {
+        check = check.known_names(known_names);
+    }
+    let mut problems = tileset.take_warnings();
+    problems.extend(check.check(&tileset));
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    let count = |severity| problems.iter().filter(|problem| problem.severity == severity).count();
+    let errors = count(Severity::Error);
+    println!("{} entries, {} errors, {} warnings, {} notes",
+        tileset.len(), errors, count(Severity::Warning), count(Severity::Note));
+    if errors > 0 {
+        return Err(PrinterError::InvalidTileset { message: format!("{} is not a valid tileset", path), source: None });
     }
+    Ok(())
+}
 
-    match args.get_one::<String>("export_tileset") {
-        Some(path) => {
-            let output = get_output_from_path(path)?;
-            match output {
-                Some(output) => {
-                    tileset.write(output)?;
-                },
-                None => {}
-            }
+fn preview_tileset(args: &ArgMatches) -> PrinterResult<()> {
+    let tileset = get_tileset(args)?;
+    let mut preview = TilesetPreview::new(&tileset)
+        .scale(*args.get_one::<u32>("scale").expect("scale default value"));
+    if let Some(columns) = args.get_one::<u32>("columns") {
+        preview = preview.columns(*columns);
+    }
+    let path = args.get_one::<String>("output_image").expect("required");
+    if let Some(writer) = get_output_from_path(path)? {
+        write_image(writer, &preview.render(), ImageFormat::Png)?;
+    }
+    Ok(())
+}
+
+fn export_tileset(args: &ArgMatches) -> PrinterResult<()> {
+    write_tileset(&get_tileset(args)?, args)
+}
+
+/// Writes to `-o` in `--format`, see `tileset_output_arg` and `output_format_arg`
+fn write_tileset(tileset: &Tileset, args: &ArgMatches) -> PrinterResult<()> {
+    let path = args.get_one::<String>("output").expect("required");
+    if let Some(output) = get_output_from_path(path)? {
+        let format = args.get_one::<String>("format")
+            .and_then(|name| TilesetFormat::from_name(name))
+            .or_else(|| TilesetFormat::from_path(path))
+            .unwrap_or(TilesetFormat::Csv);
+        tileset.write_as(output, format)?;
+    }
+    Ok(())
+}
+
+fn write_calibration_pattern(args: &ArgMatches) -> PrinterResult<()> {
+    let tileset = get_tileset(args)?;
+    let swatch = *args.get_one::<u32>("swatch").expect("swatch default value");
+    let indexed = CalibrationPattern::new(&tileset).swatch(swatch).indexed();
+    if let Some(writer) = get_output_from_path(args.get_one::<String>("output_blueprint").expect("output default value"))? {
+        FactorioBPStringBuilder::new("Calibration pattern", &indexed, &tileset)
+            .factorio_serialize_to(writer)?;
+    }
+    Ok(())
+}
+
+/// Replaces `--view` colors with ones sampled from the screenshot, the tileset has to be the one of the pattern
+fn sample_calibration(args: &ArgMatches) -> PrinterResult<()> {
+    let mut tileset = get_tileset(args)?;
+    let swatch = *args.get_one::<u32>("swatch").expect("swatch default value");
+    let path = args.get_one::<String>("screenshot").expect("required");
+    let (screenshot, _) = printer::load_image(&printer::read_all(get_input_from_path(path)?)?)?;
+    let bounds = args.get_one::<String>("box").expect("required");
+    let colors = CalibrationPattern::new(&tileset).swatch(swatch).sample(&screenshot, parse_values(bounds)?)?;
+    tileset.set_colors(get_view(args), &colors)?;
+    write_tileset(&tileset, args)
+}
+
+/// Library code leaves warnings to the caller, they go to stderr
+fn print_warnings(warnings: Vec<Problem>) {
+    for warning in warnings {
+        eprintln!("{}", warning);
+    }
+}
+
+fn get_presets() -> Presets {
+    match Presets::user_dir() {
+        Some(dir) => Presets::new().dir(dir),
+        None => Presets::new(),
+    }
+}
+
+fn list_presets() -> PrinterResult<()> {
+    for preset in get_presets().list()? {
+        match preset.source {
+            PresetSource::Builtin => println!("{}\tbuilt-in", preset.name),
+            PresetSource::File(path) => println!("{}\t{}", preset.name, path.display()),
         }
-        None => {}
     }
     Ok(())
 }
 
-fn main() {
-    let cmd = Command::new("Factorio Printer")
-        .version(VERSION)
-        .about("Factorio image (blue)printing tool\n\
-        FILE inputs support '-' for stdin\n\
-        FILE outputs support '-' for stdin, '!' to disable\
-        ")
-        .arg_required_else_help(true)
-        .disable_version_flag(true)
-        .arg(Arg::new("input_image")
-            .index(1)
-            .value_name("FILE")
-            .help("Input image file")
-            .required(false))
-        .arg(Arg::new("output_image")
-            .short('o')
-            .value_name("FILE")
-            .help("Output image")
-            .default_value("output.png"))
-        .arg(Arg::new("output_blueprint")
-             .short('b')
-             .value_name("FILE")
-             .help("Output blueprint")
-             .default_value("blueprint.txt"))
-        .arg(Arg::new("scale")
-            .short('s')
-            .long("scale")
-            .help("Scaling factor")
-            .value_parser(value_parser!(f32))
-            .default_value("1.0"))
-        .arg(Arg::new("preset")
+/// Parses `NAME=R,G,B`
+fn parse_override(value: &str) -> PrinterResult<(&str, Rgb<u8>)> {
+    match value.split_once('=') {
+        Some((name, color)) => Ok((name.trim(), parse_rgb(color)?)),
+        None => Err(PrinterError::InvalidInput(format!("expected override as NAME=R,G,B, got '{}'", value))),
+    }
+}
+
+fn get_view(args: &ArgMatches) -> ColorView {
+    args.get_one::<String>("view")
+        .and_then(|name| ColorView::from_name(name))
+        .expect("view default value")
+}
+
+/// Preset, data-raw dump and tileset files layered in this order, then filtered and overridden.
+/// Preset is the base for the others only when it's given explicitly
+fn compose_tileset(args: &ArgMatches, view: ColorView) -> PrinterResult<Tileset> {
+    let preset = args.get_one::<String>("preset").expect("preset default value");
+    let mut preset = get_presets().load(preset)?;
+    print_warnings(preset.take_warnings());
+    let explicit_preset = args.value_source("preset") == Some(ValueSource::CommandLine);
+    let mut layers = vec![];
+
+    if let Some(path) = args.get_one::<String>("from_dump") {
+        let mut filter = NameFilter::new();
+        for pattern in args.get_many::<String>("dump_include").unwrap_or_default() {
+            filter = filter.include(pattern);
+        }
+        for pattern in args.get_many::<String>("dump_exclude").unwrap_or_default() {
+            filter = filter.exclude(pattern);
+        }
+        let ground = args.get_many::<String>("ground_view").map(|roots| {
+            let mode = args.get_one::<String>("ground_color")
+                .and_then(|name| GroundColor::from_name(name))
+                .expect("ground color default value");
+            roots.fold(GroundSprites::new(mode), |ground, root| ground.root(root))
+        });
+        let mut layer = Tileset::from_dump(get_input_from_path(path)?, &filter, ground.as_ref())?;
+        print_warnings(layer.take_warnings());
+        layers.push(layer);
+    }
+
+    for path in args.get_many::<String>("tileset").unwrap_or_default() {
+        let (format, names) = get_tileset_format(path, args)?;
+        let mut layer = Tileset::read_as(get_input_from_path(path)?, format, names.as_ref())?;
+        print_warnings(layer.take_warnings());
+        layers.push(layer);
+    }
+
+    // every layer is checked on load, only a composed tileset needs another check
+    let mut composed = args.contains_id("exclude") || args.contains_id("only") || args.contains_id("override");
+    let mut layers = layers.into_iter();
+    let mut tileset = if explicit_preset {
+        preset
+    } else {
+        layers.next().unwrap_or(preset)
+    };
+    for layer in layers {
+        tileset.merge(layer)?;
+        composed = true;
+    }
+
+    let excludes = args.get_many::<String>("exclude").unwrap_or_default();
+    tileset.retain_names(&excludes.fold(NameFilter::new(), |filter, pattern| filter.exclude(pattern)))?;
+    match args.get_one::<String>("only").map(String::as_str) {
+        Some("tiles") => tileset.retain_kind(TileKind::Tile)?,
+        Some("entities") => tileset.retain_kind(TileKind::Entity)?,
+        _ => {}
+    }
+    for value in args.get_many::<String>("override").unwrap_or_default() {
+        let (name, color) = parse_override(value)?;
+        tileset.set_color(view, name, color)?;
+    }
+    if composed {
+        tileset = tileset.validated()?;
+        print_warnings(tileset.take_warnings());
+    }
+    Ok(tileset)
+}
+
+/// Composed tileset in `--view`, see `tileset_source_args`
+fn get_tileset(args: &ArgMatches) -> PrinterResult<Tileset> {
+    let view = get_view(args);
+    let mut tileset = compose_tileset(args, view)?;
+    tileset.set_view(view);
+    Ok(tileset)
+}
+
+/// Calibrated tileset with color matching set up by `print_args`
+fn get_print_tileset(args: &ArgMatches) -> PrinterResult<Tileset> {
+    let mut tileset = get_tileset(args)?;
+    let weights = args.get_one::<String>("rgb_weights").expect("rgb weights default value");
+    let weights = parse_rgb_weights(weights)?;
+    let metric = args.get_one::<String>("metric")
+        .and_then(|name| ColorMetric::from_name(name, weights))
+        .expect("metric default value");
+    if args.get_flag("linear_light") {
+        tileset.set_metric(metric.linear_light());
+    } else {
+        tileset.set_metric(metric);
+    }
+    let tie_break = args.get_one::<String>("tie_break")
+        .and_then(|name| TieBreak::from_name(name))
+        .expect("tie break default value");
+    tileset.set_tie_break(tie_break);
+    Ok(tileset)
+}
+
+fn tileset_format_arg() -> Arg {
+    Arg::new("tileset_format")
+        .long("tileset-format")
+        .value_name("FORMAT")
+        .value_parser(TilesetFormat::names())
+        .help("Format of --tileset file, guessed from extension or content by default")
+}
+
+fn tileset_names_arg() -> Arg {
+    Arg::new("tileset_names")
+        .long("tileset-names")
+        .value_name("FILE")
+        .help("CSV with name,is_tile columns naming palette colors in order, for gpl and paint-net")
+}
+
+fn view_arg() -> Arg {
+    Arg::new("view")
+        .long("view")
+        .value_name("VIEW")
+        .value_parser(ColorView::names())
+        .default_value("map")
+        .help("Tileset color profile to match against, entries without it use map colors")
+}
+
+/// Where the tileset comes from and how it's composed, see `compose_tileset`
+fn tileset_source_args() -> Vec<Arg> {
+    vec![
+        view_arg(),
+        Arg::new("preset")
             .short('p')
             .long("preset")
-            .value_parser(["base", "colorcoding"])
+            .value_name("NAME")
             .default_value("colorcoding")
-            .help("Built-in tilesets"))
-        .arg(Arg::new("tileset")
+            .help("Built-in or user preset, see 'tileset presets'. Given explicitly, --from-dump and --tileset entries are added on top of it"),
+        Arg::new("tileset")
             .short('t')
             .long("tileset")
             .value_name("FILE")
-            .help("Alternative tileset"))
-        .arg(Arg::new("export_tileset")
-            .long("export-tileset")
+            .action(ArgAction::Append)
+            .help("Alternative tileset. Repeatable, entries of later files replace same named ones"),
+        tileset_format_arg(),
+        tileset_names_arg(),
+        Arg::new("from_dump")
+            .long("from-dump")
             .value_name("FILE")
-            .help("Export current tileset in CSV format"))
-        .arg(Arg::new("alpha")
+            .help("Build tileset from data-raw-dump.json written by 'factorio --dump-data'"),
+        Arg::new("dump_include")
+            .long("dump-include")
+            .value_name("PATTERN")
+            .action(ArgAction::Append)
+            .requires("from_dump")
+            .help("Only take dump prototypes matching this name pattern, '*' and '?' wildcards. Repeatable"),
+        Arg::new("dump_exclude")
+            .long("dump-exclude")
+            .value_name("PATTERN")
+            .action(ArgAction::Append)
+            .requires("from_dump")
+            .help("Skip dump prototypes matching this name pattern. Repeatable"),
+        Arg::new("ground_view")
+            .long("ground-view")
+            .value_name("DIR")
+            .action(ArgAction::Append)
+            .requires("from_dump")
+            .help("Add ground view colors of dump tiles from their sprites in game data/ dir or mod folder. Repeatable"),
+        Arg::new("ground_color")
+            .long("ground-color")
+            .value_name("MODE")
+            .value_parser(GroundColor::names())
+            .default_value("average")
+            .help("How sprite pixels are reduced to a tile color"),
+        Arg::new("exclude")
+            .long("exclude")
+            .value_name("PATTERN")
+            .action(ArgAction::Append)
+            .help("Remove tileset entries matching this name pattern, '*' and '?' wildcards. Repeatable"),
+        Arg::new("only")
+            .long("only")
+            .value_name("KIND")
+            .value_parser(["tiles", "entities"])
+            .help("Keep only tiles or only entities of the tileset"),
+        Arg::new("override")
+            .long("override")
+            .value_name("NAME=R,G,B")
+            .action(ArgAction::Append)
+            .help("Replace --view color of one tileset entry. Repeatable"),
+    ]
+}
+
+fn swatch_arg() -> Arg {
+    Arg::new("swatch")
+        .long("swatch")
+        .value_name("SIDE")
+        .value_parser(clap::builder::RangedU64ValueParser::<u32>::new().range(1..=64))
+        .default_value("4")
+        .help("Calibration pattern swatch side in tiles")
+}
+
+fn tileset_output_arg() -> Arg {
+    Arg::new("output")
+        .short('o')
+        .value_name("FILE")
+        .required(true)
+        .help("Tileset file")
+}
+
+fn output_format_arg() -> Arg {
+    Arg::new("format")
+        .long("format")
+        .value_name("FORMAT")
+        .value_parser(TilesetFormat::names())
+        .help("Format of the tileset file, guessed from extension or CSV by default")
+}
+
+/// How an image turns into tileset entries, see `get_image_printer` and `get_print_tileset`
+fn print_args() -> Vec<Arg> {
+    vec![
+        Arg::new("scale")
+            .short('s')
+            .long("scale")
+            .help("Scaling factor")
+            .value_parser(value_parser!(f32))
+            .default_value("1.0"),
+        Arg::new("width")
+            .long("width")
+            .value_name("PIXELS")
+            .value_parser(clap::builder::RangedU64ValueParser::<u32>::new().range(1..))
+            .conflicts_with_all(["scale", "height", "fit"])
+            .help("Scale to this width, keeping aspect ratio"),
+        Arg::new("height")
+            .long("height")
+            .value_name("PIXELS")
+            .value_parser(clap::builder::RangedU64ValueParser::<u32>::new().range(1..))
+            .conflicts_with_all(["scale", "fit"])
+            .help("Scale to this height, keeping aspect ratio"),
+        Arg::new("fit")
+            .long("fit")
+            .value_name("WxH")
+            .conflicts_with("scale")
+            .help("Scale to the largest size that fits into WxH box, keeping aspect ratio"),
+        Arg::new("filter")
+            .long("filter")
+            .value_name("FILTER")
+            .value_parser(ResizeFilter::names())
+            .default_value("catmull-rom")
+            .help("Resampling filter, 'area' averages pixels and suits large downscales"),
+        Arg::new("dither")
+            .long("dither")
+            .value_name("MODE")
+            .value_parser(DitherMode::names())
+            .default_value("floyd-steinberg")
+            .help("Dithering algorithm, 'none' maps every pixel to the nearest color"),
+        Arg::new("dither_strength")
+            .long("dither-strength")
+            .value_name("VALUE")
+            .value_parser(value_parser!(f32))
+            .default_value("1.0")
+            .help("Amount of diffused error or threshold spread"),
+        Arg::new("serpentine")
+            .long("serpentine")
+            .action(ArgAction::SetTrue)
+            .help("Alternate scan direction every row for error diffusion"),
+        Arg::new("metric")
+            .long("metric")
+            .value_name("METRIC")
+            .value_parser(ColorMetric::names())
+            .default_value("rgb")
+            .help("Color distance used to pick tiles"),
+        Arg::new("rgb_weights")
+            .long("rgb-weights")
+            .value_name("R,G,B")
+            .default_value("1,1,1")
+            .help("Channel weights for 'rgb' metric, e.g. 30,59,11 for luma"),
+        Arg::new("linear_light")
+            .long("linear-light")
+            .action(ArgAction::SetTrue)
+            .help("Resize, blend and dither on linear light values, 'rgb' metric becomes 'linear-rgb'"),
+        Arg::new("tie_break")
+            .long("tie-break")
+            .value_name("RULE")
+            .value_parser(TieBreak::names())
+            .default_value("first")
+            .help("Which tile to use when several share the same color"),
+        Arg::new("alpha")
             .long("alpha")
             .value_name("VALUE")
             .value_parser(clap::builder::RangedU64ValueParser::<u8>::new().range(1..256))
             .help("Pixels with alpha channel less that <VALUE> are skipped")
-            .default_value("128"))
-        .arg(Arg::new("split")
+            .default_value("128"),
+        Arg::new("background")
+            .long("background")
+            .value_name("R,G,B")
+            .help("Blend semi-transparent pixels over this color before dithering"),
+    ]
+}
+
+/// How the blueprint string is made, see `get_blueprint_builder`
+fn blueprint_args() -> Vec<Arg> {
+    vec![
+        Arg::new("split")
             .long("split")
             .value_name("SIDE")
             .value_parser(clap::builder::RangedI64ValueParser::<i32>::new().range(0..10000))
             .help("Split blueprint into squares of <SIDE>^2 size. 0 means no splitting")
-            .default_value("0"));
+            .default_value("0"),
+        Arg::new("compression")
+            .long("compression")
+            .value_name("LEVEL")
+            .value_parser(clap::builder::RangedU64ValueParser::<u32>::new().range(0..=9))
+            .help("Blueprint zlib compression level, 0 to 9")
+            .default_value("6"),
+        Arg::new("tile_order")
+            .long("tile-order")
+            .value_name("ORDER")
+            .value_parser(TileOrder::names())
+            .help("Order of tiles in blueprint, 'grouped' gives shorter strings")
+            .default_value("scan"),
+    ]
+}
+
+fn image_arg() -> Arg {
+    Arg::new("image")
+        .index(1)
+        .value_name("FILE")
+        .required(true)
+        .help("Input image file")
+}
+
+fn blueprint_arg() -> Arg {
+    Arg::new("blueprint")
+        .index(1)
+        .value_name("FILE")
+        .required(true)
+        .help("Blueprint string file")
+}
+
+fn serve_command() -> Command {
+    Command::new("serve")
+        .about("Serve prints over HTTP: POST an image to /print for a blueprint string, \
+        a blueprint string to /render for PNG. Print options are defaults for every request")
+        .arg(Arg::new("listen")
+            .long("listen")
+            .value_name("ADDR")
+            .default_value("127.0.0.1:8080")
+            .help("Address and port to listen on"))
+        .args(print_args())
+        .args(blueprint_args())
+        .args(tileset_source_args())
+}
+
+fn main() {
+    let print_cmd = Command::new("print")
+        .about("Print an image as blueprint. Without -o and -b the blueprint string goes to stdout")
+        .arg(image_arg())
+        .arg(Arg::new("output_image")
+            .short('o')
+            .value_name("FILE")
+            .help("Preview of the print, in the format of the input image"))
+        .arg(Arg::new("output_blueprint")
+            .short('b')
+            .value_name("FILE")
+            .help("Blueprint string"))
+        .args(print_args())
+        .args(blueprint_args())
+        .args(tileset_source_args());
+    let decode_cmd = Command::new("decode")
+        .about("Decode a blueprint string into JSON")
+        .arg(blueprint_arg())
+        .arg(Arg::new("output")
+            .short('o')
+            .value_name("FILE")
+            .default_value("-")
+            .help("Blueprint JSON"));
+    let render_cmd = Command::new("render")
+        .about("Render a blueprint string back into PNG with tileset colors. Books made with --split are stitched together")
+        .arg(blueprint_arg())
+        .arg(Arg::new("output_image")
+            .short('o')
+            .value_name("FILE")
+            .required(true)
+            .help("Output PNG"))
+        .args(tileset_source_args());
+    let stats_cmd = Command::new("stats")
+        .about("Print an image and count the tiles and entities it takes, most used first, \
+        with their cost when the tileset has costs")
+        .arg(image_arg())
+        .args(print_args())
+        .args(tileset_source_args());
+    let calibrate_cmd = Command::new("calibrate")
+        .about("Calibrate tileset colors from a screenshot of a pattern built in game")
+        .subcommand_required(true)
+        .subcommand(Command::new("pattern")
+            .about("Write a blueprint with a labelled swatch of every tileset entry, left to right, top to bottom")
+            .arg(Arg::new("output_blueprint")
+                .short('o')
+                .value_name("FILE")
+                .default_value("-")
+                .help("Calibration pattern blueprint string"))
+            .arg(swatch_arg())
+            .args(tileset_source_args()))
+        .subcommand(Command::new("sample")
+            .about("Sample every swatch of a calibration pattern screenshot into the --view colors of the tileset. \
+            Takes the same tileset options the pattern was made with")
+            .arg(Arg::new("screenshot")
+                .index(1)
+                .value_name("FILE")
+                .required(true)
+                .help("Screenshot with the whole pattern in view"))
+            .arg(Arg::new("box")
+                .long("box")
+                .value_name("X,Y,W,H")
+                .required(true)
+                .help("Pattern bounds in screenshot pixels, outer edges of corner swatches"))
+            .arg(swatch_arg())
+            .arg(tileset_output_arg().required(true))
+            .arg(output_format_arg())
+            .args(tileset_source_args()));
+    let tileset_cmd = Command::new("tileset")
+        .about("Tileset tools")
+        .subcommand_required(true)
+        .subcommand(Command::new("export")
+            .about("Write the composed tileset in any tileset format")
+            .arg(tileset_output_arg())
+            .arg(output_format_arg())
+            .args(tileset_source_args()))
+        .subcommand(Command::new("check")
+            .about("Report empty tilesets, duplicate names and colors, colors too close to tell apart \
+            and names unknown to the game. Fails if there are errors")
+            .arg(Arg::new("tileset")
+                .index(1)
+                .value_name("FILE")
+                .required(true)
+                .help("Tileset to check"))
+            .arg(tileset_format_arg())
+            .arg(tileset_names_arg())
+            .arg(view_arg())
+            .arg(Arg::new("dump")
+                .long("dump")
+                .value_name("FILE")
+                .help("data-raw-dump.json written by 'factorio --dump-data', to check names against"))
+            .arg(Arg::new("min_distance")
+                .long("min-distance")
+                .value_name("DELTA_E")
+                .value_parser(value_parser!(f32))
+                .default_value("2.3")
+                .help("Colors closer than this CIEDE2000 difference are reported, 0 disables")))
+        .subcommand(Command::new("preview")
+            .about("Render the tileset as PNG legend: swatch, T for tile or E for entity, color and name of every entry. \
+            Red ! marks entries 'tileset check' reports")
+            .arg(Arg::new("output_image")
+                .short('o')
+                .value_name("FILE")
+                .required(true)
+                .help("Output PNG"))
+            .arg(Arg::new("scale")
+                .long("scale")
+                .value_name("FACTOR")
+                .value_parser(clap::builder::RangedU64ValueParser::<u32>::new().range(1..=16))
+                .default_value("2")
+                .help("Pixel size of the legend"))
+            .arg(Arg::new("columns")
+                .long("columns")
+                .value_name("COUNT")
+                .value_parser(clap::builder::RangedU64ValueParser::<u32>::new().range(1..))
+                .help("Number of columns, one per 32 entries by default"))
+            .args(tileset_source_args()))
+        .subcommand(Command::new("presets")
+            .about("List presets with where they come from. User presets are *.csv tilesets \
+            in $XDG_CONFIG_HOME/factorio-printer/presets"));
+    let cmd = Command::new("Factorio Printer")
+        .version(VERSION)
+        .about("Factorio image (blue)printing tool\n\
+        FILE inputs support '-' for stdin\n\
+        FILE outputs support '-' for stdout, '!' to disable\
+        ")
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .disable_version_flag(true)
+        .arg(Arg::new("threads")
+            .long("threads")
+            .value_name("COUNT")
+            .value_parser(value_parser!(usize))
+            .global(true)
+            .help("Worker threads, 0 means one per CPU core. Output doesn't depend on it")
+            .default_value("0"))
+        .subcommand(print_cmd)
+        .subcommand(decode_cmd)
+        .subcommand(render_cmd)
+        .subcommand(tileset_cmd)
+        .subcommand(stats_cmd)
+        .subcommand(calibrate_cmd)
+        .subcommand(serve_command());
     let args = cmd.get_matches();
-    match parse_args(&args) {
-        Ok(_) => {}
-        Err(e) => {
-            eprintln!("error: {}", e);
-            std::process::exit(-1)
+    if let Err(e) = run(&args) {
+        eprintln!("error: {}", e);
+        let mut source = e.source();
+        while let Some(cause) = source {
+            eprintln!("caused by: {}", cause);
+            source = cause.source();
         }
+        std::process::exit(e.exit_code())
+    }
+}
+
+fn run(args: &ArgMatches) -> PrinterResult<()> {
+    let threads = *args.get_one::<usize>("threads").expect("threads default value");
+    rayon::ThreadPoolBuilder::new().num_threads(threads).build_global()
+        .map_err(|e| PrinterError::InvalidInput(format!("can't start {} worker threads: {}", threads, e)))?;
+
+    match args.subcommand() {
+        Some(("print", args)) => print_image(args),
+        Some(("decode", args)) => decode_blueprint(args),
+        Some(("render", args)) => render_blueprint(args),
+        Some(("stats", args)) => image_stats(args),
+        Some(("serve", args)) => serve::serve(args),
+        Some(("calibrate", args)) => match args.subcommand() {
+            Some(("pattern", args)) => write_calibration_pattern(args),
+            Some(("sample", args)) => sample_calibration(args),
+            _ => unreachable!("subcommand is required"),
+        },
+        Some(("tileset", args)) => match args.subcommand() {
+            Some(("export", args)) => export_tileset(args),
+            Some(("check", args)) => check_tileset(args),
+            Some(("preview", args)) => preview_tileset(args),
+            Some(("presets", _)) => list_presets(),
+            _ => unreachable!("subcommand is required"),
+        },
+        _ => unreachable!("subcommand is required"),
     }
 }
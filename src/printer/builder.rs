@@ -0,0 +1,82 @@
+use std::error::Error;
+use std::fmt;
+use crate::printer::{Tile, Tileset};
+use crate::printer::indexed::MAX_ENTRIES;
+
+/// What can go wrong when building or changing a tileset in code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TilesetError {
+    /// Tileset would have no entries left
+    Empty,
+    /// Name or alias is already used by another entry
+    DuplicateName(String),
+    /// No entry has this name or alias
+    UnknownName(String),
+    /// Got a different number of colors than there are entries
+    ColorCount { expected: usize, got: usize },
+    /// More entries than an `IndexedImage` can tell apart
+    TooLarge(usize),
+}
+
+impl fmt::Display for TilesetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TilesetError::Empty => write!(f, "tileset has no entries"),
+            TilesetError::DuplicateName(name) => write!(f, "name '{}' is already in the tileset", name),
+            TilesetError::UnknownName(name) => write!(f, "'{}' is not in the tileset", name),
+            TilesetError::ColorCount { expected, got } =>
+                write!(f, "got {} colors for {} tileset entries", got, expected),
+            TilesetError::TooLarge(count) =>
+                write!(f, "tileset has {} entries, at most {} are supported", count, MAX_ENTRIES),
+        }
+    }
+}
+
+impl Error for TilesetError {}
+
+/// First name or alias of `tile` that one of `tiles` already uses
+pub(super) fn taken_name<'a>(tiles: &[Tile], tile: &'a Tile) -> Option<&'a str> {
+    std::iter::once(&tile.name)
+        .chain(&tile.aliases)
+        .find(|name| tiles.iter().any(|other| other.is_named(name)))
+        .map(String::as_str)
+}
+
+/// Fails if a tileset of `count` entries is too large to print with
+pub(super) fn check_size(count: usize) -> Result<(), TilesetError> {
+    if count > MAX_ENTRIES {
+        return Err(TilesetError::TooLarge(count));
+    }
+    Ok(())
+}
+
+/// Builds a tileset in code, entries keep the order they are added in
+#[derive(Debug, Default)]
+pub struct TilesetBuilder {
+    tiles: Vec<Tile>,
+}
+
+impl TilesetBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn tile(mut self, tile: Tile) -> Self {
+        self.tiles.push(tile);
+        self
+    }
+
+    /// Fails if there are no entries, too many of them or some name or alias is used twice
+    pub fn build(self) -> Result<Tileset, TilesetError> {
+        if self.tiles.is_empty() {
+            return Err(TilesetError::Empty);
+        }
+        check_size(self.tiles.len())?;
+        for (index, tile) in self.tiles.iter().enumerate() {
+            if let Some(name) = taken_name(&self.tiles[..index], tile) {
+                return Err(TilesetError::DuplicateName(name.to_string()));
+            }
+        }
+        Ok(Tileset::from_tiles(self.tiles))
+    }
+}
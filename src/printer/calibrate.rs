@@ -0,0 +1,129 @@
+use image::{Rgb, RgbaImage};
+use crate::printer::{IndexedImage, PrinterError, Tileset};
+use crate::printer::color::median_color;
+use crate::printer::font::{text_pixels, text_width, GLYPH_HEIGHT};
+use crate::PrinterResult;
+
+const DEFAULT_SWATCH_SIDE: u32 = 4;
+const SWATCH_GAP: u32 = 1;
+/// Rows between a swatch and its label
+const LABEL_GAP: u32 = 1;
+/// Part of the swatch side skipped on every edge when sampling, keeps neighbours and grid lines out
+const SAMPLE_MARGIN: f64 = 0.25;
+
+/// Grid of square swatches, one per tileset entry in tileset order, row by row.
+/// Every swatch has its entry number written under it in the same tile.
+/// Print it, take a screenshot and feed it back to `sample` to get real in-game colors
+pub struct CalibrationPattern {
+    count: u32,
+    columns: u32,
+    swatch: u32,
+}
+
+impl CalibrationPattern {
+    pub fn new(tileset: &Tileset) -> Self {
+        let count = tileset.len() as u32;
+        CalibrationPattern {
+            count,
+            columns: (count as f64).sqrt().ceil().max(1.0) as u32,
+            swatch: DEFAULT_SWATCH_SIDE,
+        }
+    }
+
+    /// Swatch side in tiles
+    pub fn swatch(mut self, value: u32) -> Self {
+        self.swatch = value.max(1);
+        self
+    }
+
+    fn rows(&self) -> u32 {
+        (self.count as f64 / self.columns as f64).ceil().max(1.0) as u32
+    }
+
+    /// Label of swatch `index`, entries are counted from 1
+    fn label(index: u32) -> String {
+        (index + 1).to_string()
+    }
+
+    /// Swatch and the widest label side by side
+    fn cell_width(&self) -> u32 {
+        self.swatch.max(text_width(&Self::label(self.count.saturating_sub(1))))
+    }
+
+    /// Distance between top left tiles of neighbouring swatches
+    fn step(&self) -> (u32, u32) {
+        (self.cell_width() + SWATCH_GAP, self.swatch + LABEL_GAP + GLYPH_HEIGHT + SWATCH_GAP)
+    }
+
+    /// Pattern size in tiles, from the first swatch corner to the last one. Labels of the last row are left out
+    pub fn dimensions(&self) -> (u32, u32) {
+        let (step_x, step_y) = self.step();
+        ((self.columns - 1) * step_x + self.swatch, (self.rows() - 1) * step_y + self.swatch)
+    }
+
+    /// Top left tile of swatch `index`
+    fn origin(&self, index: u32) -> (u32, u32) {
+        let (step_x, step_y) = self.step();
+        ((index % self.columns) * step_x, (index / self.columns) * step_y)
+    }
+
+    /// Pattern ready for `FactorioBPStringBuilder`, gaps are left empty
+    pub fn indexed(&self) -> IndexedImage {
+        let (step_x, step_y) = self.step();
+        let width = (self.columns - 1) * step_x + self.cell_width();
+        let height = self.rows() * step_y - SWATCH_GAP;
+        let mut indexed = IndexedImage::new(width, height);
+        let mut rows: Vec<_> = indexed.rows_mut().collect();
+        for index in 0..self.count {
+            let (x0, y0) = self.origin(index);
+            for y in y0..y0 + self.swatch {
+                for x in x0..x0 + self.swatch {
+                    rows[y as usize].set(x, index as usize);
+                }
+            }
+            let label_top = y0 + self.swatch + LABEL_GAP;
+            for (x, y) in text_pixels(&Self::label(index)) {
+                rows[(label_top + y) as usize].set(x0 + x, index as usize);
+            }
+        }
+        indexed
+    }
+
+    /// Median color of the middle of every swatch. `bounds` is (x, y, width, height)
+    /// of the whole pattern in `screenshot` pixels
+    pub fn sample(&self, screenshot: &RgbaImage, bounds: [u32; 4]) -> PrinterResult<Vec<Rgb<u8>>> {
+        let [left, top, width, height] = bounds;
+        let fits = |start: u32, size: u32, limit: u32| start.checked_add(size).is_some_and(|end| end <= limit);
+        if width == 0 || height == 0
+            || !fits(left, width, screenshot.width()) || !fits(top, height, screenshot.height()) {
+            return Err(PrinterError::InvalidInput(format!(
+                "pattern box {}x{} at {},{} doesn't fit into {}x{} screenshot",
+                width, height, left, top, screenshot.width(), screenshot.height()
+            )));
+        }
+        let (tiles_x, tiles_y) = self.dimensions();
+        let scale_x = width as f64 / tiles_x as f64;
+        let scale_y = height as f64 / tiles_y as f64;
+        let margin = self.swatch as f64 * SAMPLE_MARGIN;
+        (0..self.count).map(|index| {
+            let (x0, y0) = self.origin(index);
+            let from_x = left + ((x0 as f64 + margin) * scale_x).round() as u32;
+            let to_x = left + ((x0 as f64 + self.swatch as f64 - margin) * scale_x).round() as u32;
+            let from_y = top + ((y0 as f64 + margin) * scale_y).round() as u32;
+            let to_y = top + ((y0 as f64 + self.swatch as f64 - margin) * scale_y).round() as u32;
+            let mut pixels: Vec<[u8; 3]> = (from_y..to_y)
+                .flat_map(|y| (from_x..to_x).map(move |x| (x, y)))
+                .map(|(x, y)| {
+                    let [r, g, b, _] = screenshot.get_pixel(x, y).0;
+                    [r, g, b]
+                })
+                .collect();
+            if pixels.is_empty() {
+                return Err(PrinterError::InvalidInput(
+                    format!("pattern box is too small to sample swatch {}", index + 1)
+                ));
+            }
+            Ok(Rgb::from(median_color(&mut pixels)))
+        }).collect()
+    }
+}
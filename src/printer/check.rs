@@ -0,0 +1,190 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::io::Read;
+use image::Rgb;
+use crate::printer::{read_text, ColorMetric, TileKind, Tileset};
+use crate::printer::dump::prototype_names;
+use crate::printer::tile::HexColor;
+use crate::PrinterResult;
+
+/// ΔE*00 below which two colors are hard to tell apart
+const DEFAULT_MIN_DISTANCE: f32 = 2.3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Tileset can't be used as is, loading fails
+    Error,
+    /// Probably a mistake
+    Warning,
+    /// Worth a look, not shown on load
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        })
+    }
+}
+
+/// One finding of `TilesetCheck`
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub severity: Severity,
+    /// Index of the entry in tileset order, `None` for the whole tileset
+    pub entry: Option<usize>,
+    /// `line N` for line based formats, `entry N` otherwise, empty for the whole tileset
+    pub location: String,
+    pub message: String,
+}
+
+impl Problem {
+    /// Warning about the whole tileset or something left out of it
+    pub(crate) fn warning(message: String) -> Self {
+        Problem { severity: Severity::Warning, entry: None, location: String::new(), message }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.location.is_empty() {
+            write!(f, "{}: {}", self.severity, self.message)
+        } else {
+            write!(f, "{}: {}: {}", self.severity, self.location, self.message)
+        }
+    }
+}
+
+/// Tile and other prototype names of a data-raw dump, to check tileset names against
+pub struct PrototypeNames {
+    tiles: BTreeSet<String>,
+    entities: BTreeSet<String>,
+}
+
+impl PrototypeNames {
+    /// Reads `data-raw-dump.json` written by `factorio --dump-data`
+    pub fn from_dump(src: Box<dyn Read>) -> PrinterResult<Self> {
+        let data = read_text(src, "data-raw dump")?;
+        let (tiles, entities) = prototype_names(&data)?;
+        Ok(PrototypeNames { tiles, entities })
+    }
+}
+
+/// Looks for empty tilesets, duplicate names and colors, colors too close to tell apart
+/// and, with `known_names`, names the game doesn't have. Colors are checked in the current view
+pub struct TilesetCheck<'a> {
+    min_distance: f32,
+    known_names: Option<&'a PrototypeNames>,
+}
+
+impl Default for TilesetCheck<'_> {
+    fn default() -> Self {
+        TilesetCheck { min_distance: DEFAULT_MIN_DISTANCE, known_names: None }
+    }
+}
+
+impl<'a> TilesetCheck<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// ΔE*00 below which different colors are reported, 0 disables the check
+    pub fn min_distance(mut self, value: f32) -> Self {
+        self.min_distance = value;
+        self
+    }
+
+    pub fn known_names(mut self, names: &'a PrototypeNames) -> Self {
+        self.known_names = Some(names);
+        self
+    }
+
+    /// Problems ordered by entry, most severe first within an entry
+    pub fn check(&self, tileset: &Tileset) -> Vec<Problem> {
+        let tiles = &tileset.tiles;
+        if tiles.is_empty() {
+            return vec![Problem {
+                severity: Severity::Error,
+                entry: None,
+                location: String::new(),
+                message: "tileset has no entries".to_string(),
+            }];
+        }
+        let location = |index: usize| match tiles[index].line {
+            Some(line) => format!("line {}", line),
+            None => format!("entry {}", index + 1),
+        };
+        let mut found = vec![];
+        let mut report = |index: usize, severity: Severity, message: String| {
+            found.push(Problem { severity, entry: Some(index), location: location(index), message });
+        };
+
+        let mut names: HashMap<&str, usize> = HashMap::new();
+        for (index, tile) in tiles.iter().enumerate() {
+            for name in std::iter::once(&tile.name).chain(&tile.aliases) {
+                match names.get(name.as_str()) {
+                    Some(first) => report(index, Severity::Error, format!(
+                        "name '{}' is already used on {}", name, location(*first)
+                    )),
+                    None => { names.insert(name, index); }
+                }
+            }
+        }
+
+        let colors: Vec<_> = (0..tiles.len()).map(|index| HexColor(tileset.color(index).0)).collect();
+        let mut first_with: HashMap<HexColor, usize> = HashMap::new();
+        let mut distinct = vec![];
+        for (index, color) in colors.iter().enumerate() {
+            match first_with.get(color) {
+                Some(first) => report(index, Severity::Warning, format!(
+                    "color {} is the same as on {}, tie break decides which one is used", color, location(*first)
+                )),
+                None => {
+                    first_with.insert(*color, index);
+                    distinct.push(index);
+                }
+            }
+        }
+        // validated() on load turns this off, pairs are quadratic
+        if self.min_distance > 0.0 {
+            let metric = ColorMetric::Ciede2000;
+            let lab: Vec<_> = colors.iter().map(|color| metric.prepare(&Rgb(color.0))).collect();
+            let min_distance = self.min_distance * self.min_distance;
+            for (position, &index) in distinct.iter().enumerate() {
+                let closest = distinct[..position].iter()
+                    .map(|&other| (other, metric.distance(&lab[index], &lab[other])))
+                    .filter(|(_, distance)| *distance < min_distance)
+                    .min_by(|(_, a), (_, b)| a.total_cmp(b));
+                if let Some((other, distance)) = closest {
+                    report(index, Severity::Note, format!(
+                        "color {} is hard to tell apart from {} (ΔE {:.1})", colors[index], location(other), distance.sqrt()
+                    ));
+                }
+            }
+        }
+        if let Some(known) = self.known_names {
+            for (index, tile) in tiles.iter().enumerate() {
+                let (own, other, kind, other_kind) = match tile.kind {
+                    TileKind::Tile => (&known.tiles, &known.entities, "a tile", "an entity"),
+                    TileKind::Entity => (&known.entities, &known.tiles, "an entity", "a tile"),
+                };
+                if own.contains(&tile.name) {
+                    continue;
+                }
+                if other.contains(&tile.name) {
+                    report(index, Severity::Error, format!(
+                        "'{}' is {} in the dump, but {} here", tile.name, other_kind, kind
+                    ));
+                } else {
+                    report(index, Severity::Warning, format!("'{}' is not in the dump", tile.name));
+                }
+            }
+        }
+
+        found.sort_by_key(|problem| (problem.entry, problem.severity));
+        found
+    }
+}
@@ -0,0 +1,262 @@
+use image::{Rgb, RgbaImage};
+
+/// How "close" two colors are when picking a tile for a pixel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorMetric {
+    /// Squared sRGB distance with per-channel weights
+    WeightedRgb([f32; 3]),
+    /// Same as `WeightedRgb`, but on linear light values
+    LinearRgb([f32; 3]),
+    /// Cheap perceptual approximation, see <https://www.compuphase.com/cmetric.htm>
+    Redmean,
+    /// Euclidean distance in CIELAB (ΔE*76)
+    Cie76,
+    /// CIEDE2000 (ΔE*00)
+    Ciede2000,
+    /// Euclidean distance in OKLab
+    Oklab,
+}
+
+const METRIC_NAMES: [&str; 6] = ["rgb", "linear-rgb", "redmean", "cie76", "ciede2000", "oklab"];
+
+impl Default for ColorMetric {
+    fn default() -> Self {
+        ColorMetric::WeightedRgb([1.0; 3])
+    }
+}
+
+impl ColorMetric {
+    pub fn names() -> Vec<&'static str> {
+        METRIC_NAMES.to_vec()
+    }
+
+    /// `rgb_weights` only matters for the "rgb" and "linear-rgb" metrics
+    pub fn from_name(name: &str, rgb_weights: [f32; 3]) -> Option<Self> {
+        match name {
+            "rgb" => Some(ColorMetric::WeightedRgb(rgb_weights)),
+            "linear-rgb" => Some(ColorMetric::LinearRgb(rgb_weights)),
+            "redmean" => Some(ColorMetric::Redmean),
+            "cie76" => Some(ColorMetric::Cie76),
+            "ciede2000" => Some(ColorMetric::Ciede2000),
+            "oklab" => Some(ColorMetric::Oklab),
+            _ => None,
+        }
+    }
+
+    /// Metric to use with a linear light pipeline. Plain RGB distance becomes linear,
+    /// the perceptual ones decode sRGB on their own and stay as they are
+    pub fn linear_light(self) -> Self {
+        match self {
+            ColorMetric::WeightedRgb(w) => ColorMetric::LinearRgb(w),
+            metric => metric,
+        }
+    }
+
+    /// Converts color into the space `distance` works in.
+    /// Tileset colors are prepared once, pixels on every lookup
+    pub(crate) fn prepare(&self, color: &Rgb<u8>) -> [f32; 3] {
+        match self {
+            ColorMetric::WeightedRgb(_) | ColorMetric::Redmean => [
+                color.0[0] as f32,
+                color.0[1] as f32,
+                color.0[2] as f32,
+            ],
+            ColorMetric::LinearRgb(_) => [
+                (srgb_to_linear(color.0[0]) * 255.0) as f32,
+                (srgb_to_linear(color.0[1]) * 255.0) as f32,
+                (srgb_to_linear(color.0[2]) * 255.0) as f32,
+            ],
+            ColorMetric::Cie76 | ColorMetric::Ciede2000 => srgb_to_lab(color),
+            ColorMetric::Oklab => srgb_to_oklab(color),
+        }
+    }
+
+    /// Per-axis weights `w` such that `w[axis] * d[axis]^2` never exceeds `distance`,
+    /// where `d` is the difference of prepared colors. `None` if there are none
+    pub(crate) fn axis_bounds(&self) -> Option<[f32; 3]> {
+        match self {
+            ColorMetric::WeightedRgb(w) | ColorMetric::LinearRgb(w)
+                if w.iter().all(|w| *w >= 0.0) => Some(*w),
+            ColorMetric::WeightedRgb(_) | ColorMetric::LinearRgb(_) => None,
+            // mean red is within 0..255, so red and blue factors are at least 2
+            ColorMetric::Redmean => Some([2.0, 4.0, 2.0]),
+            ColorMetric::Cie76 | ColorMetric::Oklab => Some([1.0; 3]),
+            ColorMetric::Ciede2000 => None,
+        }
+    }
+
+    /// Distance between two prepared colors, only the ordering is meaningful
+    pub(crate) fn distance(&self, a: &[f32; 3], b: &[f32; 3]) -> f32 {
+        let d = [a[0] - b[0], a[1] - b[1], a[2] - b[2]];
+        match self {
+            ColorMetric::WeightedRgb(w) | ColorMetric::LinearRgb(w) =>
+                d[0] * d[0] * w[0] + d[1] * d[1] * w[1] + d[2] * d[2] * w[2],
+            ColorMetric::Redmean => {
+                let mean_red = (a[0] + b[0]) / 2.0;
+                (2.0 + mean_red / 256.0) * d[0] * d[0]
+                    + 4.0 * d[1] * d[1]
+                    + (2.0 + (255.0 - mean_red) / 256.0) * d[2] * d[2]
+            }
+            ColorMetric::Cie76 | ColorMetric::Oklab =>
+                d[0] * d[0] + d[1] * d[1] + d[2] * d[2],
+            ColorMetric::Ciede2000 => ciede2000(a, b),
+        }
+    }
+}
+
+pub(crate) fn srgb_to_linear(value: u8) -> f64 {
+    let v = value as f64 / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Inverse of `srgb_to_linear`, `value` is clamped to 0..1
+pub(crate) fn linear_to_srgb(value: f64) -> u8 {
+    let v = value.clamp(0.0, 1.0);
+    let v = if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    };
+    (v * 255.0).round() as u8
+}
+
+/// `srgb_to_linear` for every 8-bit value
+pub(crate) fn srgb_to_linear_table() -> [f32; 256] {
+    let mut table = [0f32; 256];
+    for (value, linear) in table.iter_mut().enumerate() {
+        *linear = srgb_to_linear(value as u8) as f32;
+    }
+    table
+}
+
+/// Per-channel median, `pixels` get reordered
+pub(crate) fn median_color(pixels: &mut [[u8; 3]]) -> [u8; 3] {
+    let middle = pixels.len() / 2;
+    [0, 1, 2].map(|c| {
+        pixels.select_nth_unstable_by_key(middle, |pixel| pixel[c]);
+        pixels[middle][c]
+    })
+}
+
+/// Blends semi-transparent pixels over `background`, alpha is kept as is.
+/// With `linear_light` blending is done on linear values instead of sRGB ones
+pub fn composite_background(image: &mut RgbaImage, background: Rgb<u8>, linear_light: bool) {
+    let table = srgb_to_linear_table();
+    for pix in image.pixels_mut() {
+        let alpha = pix.0[3] as u32;
+        for c in 0..3 {
+            pix.0[c] = if linear_light {
+                let a = alpha as f64 / 255.0;
+                let blended = table[pix.0[c] as usize] as f64 * a
+                    + table[background.0[c] as usize] as f64 * (1.0 - a);
+                linear_to_srgb(blended)
+            } else {
+                let blended = pix.0[c] as u32 * alpha + background.0[c] as u32 * (255 - alpha);
+                ((blended + 127) / 255) as u8
+            };
+        }
+    }
+}
+
+fn srgb_to_lab(color: &Rgb<u8>) -> [f32; 3] {
+    let r = srgb_to_linear(color.0[0]);
+    let g = srgb_to_linear(color.0[1]);
+    let b = srgb_to_linear(color.0[2]);
+    // D65 white point
+    let x = (0.4124564 * r + 0.3575761 * g + 0.1804375 * b) / 0.95047;
+    let y = 0.2126729 * r + 0.7151522 * g + 0.0721750 * b;
+    let z = (0.0193339 * r + 0.1191920 * g + 0.9503041 * b) / 1.08883;
+    let f = |t: f64| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [(116.0 * fy - 16.0) as f32, (500.0 * (fx - fy)) as f32, (200.0 * (fy - fz)) as f32]
+}
+
+fn srgb_to_oklab(color: &Rgb<u8>) -> [f32; 3] {
+    let r = srgb_to_linear(color.0[0]);
+    let g = srgb_to_linear(color.0[1]);
+    let b = srgb_to_linear(color.0[2]);
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    [
+        (0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s) as f32,
+        (1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s) as f32,
+        (0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s) as f32,
+    ]
+}
+
+/// Squared ΔE*00 of two CIELAB colors, see Sharma et al. 2005
+fn ciede2000(lab1: &[f32; 3], lab2: &[f32; 3]) -> f32 {
+    let [l1, a1, b1] = *lab1;
+    let [l2, a2, b2] = *lab2;
+    let c1 = (a1 * a1 + b1 * b1).sqrt();
+    let c2 = (a2 * a2 + b2 * b2).sqrt();
+    let c_mean7 = ((c1 + c2) / 2.0).powi(7);
+    let g = 0.5 * (1.0 - (c_mean7 / (c_mean7 + 25f32.powi(7))).sqrt());
+    let a1p = a1 * (1.0 + g);
+    let a2p = a2 * (1.0 + g);
+    let c1p = (a1p * a1p + b1 * b1).sqrt();
+    let c2p = (a2p * a2p + b2 * b2).sqrt();
+    let hue = |b: f32, a: f32| {
+        if a == 0.0 && b == 0.0 {
+            0.0
+        } else {
+            let h = b.atan2(a).to_degrees();
+            if h < 0.0 { h + 360.0 } else { h }
+        }
+    };
+    let h1p = hue(b1, a1p);
+    let h2p = hue(b2, a2p);
+
+    let dl = l2 - l1;
+    let dc = c2p - c1p;
+    let dh = if c1p * c2p == 0.0 {
+        0.0
+    } else if (h2p - h1p).abs() <= 180.0 {
+        h2p - h1p
+    } else if h2p - h1p > 180.0 {
+        h2p - h1p - 360.0
+    } else {
+        h2p - h1p + 360.0
+    };
+    let dh_big = 2.0 * (c1p * c2p).sqrt() * (dh.to_radians() / 2.0).sin();
+
+    let l_mean = (l1 + l2) / 2.0;
+    let c_mean = (c1p + c2p) / 2.0;
+    let h_mean = if c1p * c2p == 0.0 {
+        h1p + h2p
+    } else if (h1p - h2p).abs() <= 180.0 {
+        (h1p + h2p) / 2.0
+    } else if h1p + h2p < 360.0 {
+        (h1p + h2p + 360.0) / 2.0
+    } else {
+        (h1p + h2p - 360.0) / 2.0
+    };
+    let t = 1.0 - 0.17 * (h_mean - 30.0).to_radians().cos()
+        + 0.24 * (2.0 * h_mean).to_radians().cos()
+        + 0.32 * (3.0 * h_mean + 6.0).to_radians().cos()
+        - 0.20 * (4.0 * h_mean - 63.0).to_radians().cos();
+    let d_theta = 30.0 * (-((h_mean - 275.0) / 25.0).powi(2)).exp();
+    let c_mean_p7 = c_mean.powi(7);
+    let rc = 2.0 * (c_mean_p7 / (c_mean_p7 + 25f32.powi(7))).sqrt();
+    let l50 = (l_mean - 50.0) * (l_mean - 50.0);
+    let sl = 1.0 + 0.015 * l50 / (20.0 + l50).sqrt();
+    let sc = 1.0 + 0.045 * c_mean;
+    let sh = 1.0 + 0.015 * c_mean * t;
+    let rt = -(2.0 * d_theta).to_radians().sin() * rc;
+
+    let tl = dl / sl;
+    let tc = dc / sc;
+    let th = dh_big / sh;
+    tl * tl + tc * tc + th * th + rt * tc * th
+}
@@ -0,0 +1,186 @@
+use std::io::{BufReader, Read};
+use image::{Pixel, Rgba, RgbaImage};
+use flate2::read::ZlibDecoder;
+use base64::engine::general_purpose::STANDARD as B64Engine;
+use base64::Engine;
+use crate::printer::schema::{FactorioBlueprintInternal, FactorioBlueprintString, FactorioBookInternal};
+use crate::printer::{PrinterError, Problem, Tileset, MAX_IMAGE_PIXELS};
+use crate::PrinterResult;
+
+/// Largest inflated blueprint JSON `factorio_deserialize` parses
+pub const MAX_BLUEPRINT_BYTES: u64 = 1 << 28;
+
+/// Reverse of `FactorioBPStringBuilder::factorio_serialize`:
+/// strips version byte, inflates and parses the JSON. Fails on JSON over `MAX_BLUEPRINT_BYTES`
+pub fn factorio_deserialize(data: &str) -> PrinterResult<FactorioBlueprintString> {
+    factorio_deserialize_within(data, MAX_BLUEPRINT_BYTES)
+}
+
+/// `factorio_deserialize` for blueprints that inflate to at most `max_bytes` of JSON,
+/// inflating stops as soon as they are exceeded
+pub fn factorio_deserialize_within(data: &str, max_bytes: u64) -> PrinterResult<FactorioBlueprintString> {
+    let data = data.trim();
+    let payload = match data.strip_prefix('0') {
+        Some(payload) => payload,
+        None => return Err(PrinterError::BlueprintDecode {
+            message: "unsupported blueprint string version".to_string(),
+            source: None,
+        }),
+    };
+    let compr = B64Engine.decode(payload)
+        .map_err(|e| PrinterError::blueprint_decode("blueprint string is not valid base64", e))?;
+    let mut decoder = ZlibDecoder::new(compr.as_slice()).take(max_bytes.saturating_add(1));
+    let parsed = serde_json::from_reader(BufReader::new(&mut decoder));
+    // the whole budget and one byte more got read, whatever the parser made of it
+    if decoder.limit() == 0 {
+        return Err(PrinterError::BlueprintDecode {
+            message: format!("blueprint inflates to more than {} bytes", max_bytes),
+            source: None,
+        });
+    }
+    parsed.map_err(|e| PrinterError::blueprint_decode("can't parse blueprint", e))
+}
+
+/// Blueprint labels produced by `--split` end with "x: X y: Y"
+fn parse_split_coords(label: &str) -> Option<(i32, i32)> {
+    let (_, coords) = label.rsplit_once(": x: ")?;
+    let (x, y) = coords.split_once(" y: ")?;
+    Some((x.trim().parse().ok()?, y.trim().parse().ok()?))
+}
+
+pub struct BlueprintRenderer<'a> {
+    blueprint: &'a FactorioBlueprintString,
+    tileset: &'a Tileset,
+    max_pixels: u64,
+}
+
+impl BlueprintRenderer<'_> {
+    pub fn new<'a>(
+        blueprint: &'a FactorioBlueprintString,
+        tileset: &'a Tileset
+    ) -> BlueprintRenderer<'a> {
+        BlueprintRenderer { blueprint, tileset, max_pixels: MAX_IMAGE_PIXELS }
+    }
+
+    /// `render` fails when the blueprint spans more pixels, `MAX_IMAGE_PIXELS` by default
+    pub fn max_pixels(mut self, value: u64) -> Self {
+        self.max_pixels = value;
+        self
+    }
+
+    fn blueprint_extent(bp: &FactorioBlueprintInternal) -> i64 {
+        let tiles = bp.tiles.iter().map(|t| t.position.cell());
+        let entities = bp.entities.iter().map(|e| e.position.cell());
+        tiles.chain(entities).map(|(x, y)| x.max(y) as i64).max().unwrap_or(0) + 1
+    }
+
+    /// Split square side of a book page. Pages made by `--split` snap to a grid of that size,
+    /// older ones only have their contents to go by, `fallback` is the largest of them
+    fn page_side(bp: &FactorioBlueprintInternal, fallback: i64) -> (i64, i64) {
+        match &bp.snap_to_grid {
+            Some(grid) => {
+                let (x, y) = grid.cell();
+                (x.max(1) as i64, y.max(1) as i64)
+            }
+            None => (fallback, fallback),
+        }
+    }
+
+    /// Blueprints of a book and all of its nested books, in order
+    fn book_blueprints<'b>(book: &'b FactorioBookInternal, out: &mut Vec<&'b FactorioBlueprintInternal>) {
+        for element in &book.blueprints {
+            match &element.content {
+                FactorioBlueprintString::Blueprint(bp) => out.push(bp),
+                FactorioBlueprintString::BlueprintBook(book) => Self::book_blueprints(book, out),
+                _ => {}
+            }
+        }
+    }
+
+    /// Blueprints to draw with their offsets, pages of a book are laid out by their split coordinates
+    fn pages(&self) -> PrinterResult<Vec<(&FactorioBlueprintInternal, i64, i64)>> {
+        match self.blueprint {
+            FactorioBlueprintString::Blueprint(bp) => Ok(vec![(bp, 0, 0)]),
+            FactorioBlueprintString::BlueprintBook(book) => {
+                let mut prints = vec![];
+                Self::book_blueprints(book, &mut prints);
+                let fallback = prints.iter()
+                    .map(|bp| Self::blueprint_extent(bp))
+                    .max()
+                    .unwrap_or(0);
+                Ok(prints.into_iter().enumerate().map(|(index, bp)| {
+                    let (x, y) = bp.label.as_deref()
+                        .and_then(parse_split_coords)
+                        .unwrap_or((index as i32, 0));
+                    let (side_x, side_y) = Self::page_side(bp, fallback);
+                    (bp, x as i64 * side_x, y as i64 * side_y)
+                }).collect())
+            }
+            _ => Err(PrinterError::BlueprintDecode {
+                message: "planners have nothing to render".to_string(),
+                source: None,
+            }),
+        }
+    }
+
+    /// Cell of every tile and entity with its tileset entry, `None` for names missing from the tileset.
+    /// Entities go last so they are drawn on top of tiles. Coordinates are wide enough not to overflow
+    fn cells<'b>(
+        &'b self,
+        pages: &'b [(&'b FactorioBlueprintInternal, i64, i64)]
+    ) -> impl Iterator<Item = (i64, i64, &'b String, Option<usize>)> + 'b {
+        pages.iter().flat_map(move |(bp, offset_x, offset_y)| {
+            let tiles = bp.tiles.iter().map(|t| (&t.name, &t.position));
+            let entities = bp.entities.iter().map(|e| (&e.name, &e.position));
+            tiles.chain(entities).map(move |(name, position)| {
+                let (x, y) = position.cell();
+                (x as i64 + offset_x, y as i64 + offset_y, name, self.tileset.find_by_name(name))
+            })
+        })
+    }
+
+    /// Names missing from the tileset are skipped, each comes back as a warning.
+    /// The extent is checked against `max_pixels` before anything is drawn
+    pub fn render(&self) -> PrinterResult<(RgbaImage, Vec<Problem>)> {
+        let pages = self.pages()?;
+        let mut bounds: Option<(i64, i64, i64, i64)> = None;
+        let mut unknown: Vec<&String> = vec![];
+        for (x, y, name, index) in self.cells(&pages) {
+            match index {
+                Some(_) => bounds = Some(match bounds {
+                    Some((min_x, min_y, max_x, max_y)) => (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y)),
+                    None => (x, y, x, y),
+                }),
+                None => {
+                    if !unknown.contains(&name) {
+                        unknown.push(name);
+                    }
+                }
+            }
+        }
+        let Some((min_x, min_y, max_x, max_y)) = bounds else {
+            return Err(PrinterError::BlueprintDecode {
+                message: "blueprint has nothing to render with current tileset".to_string(),
+                source: None,
+            });
+        };
+
+        let (width, height) = ((max_x - min_x + 1) as u64, (max_y - min_y + 1) as u64);
+        if width.saturating_mul(height) > self.max_pixels || width.max(height) > u32::MAX as u64 {
+            return Err(PrinterError::BlueprintDecode {
+                message: format!("blueprint spans {}x{}, at most {} pixels can be rendered", width, height, self.max_pixels),
+                source: None,
+            });
+        }
+        let mut image = RgbaImage::from_pixel(width as u32, height as u32, Rgba::from([0; 4]));
+        for (x, y, _, index) in self.cells(&pages) {
+            if let Some(index) = index {
+                image.put_pixel((x - min_x) as u32, (y - min_y) as u32, self.tileset.color(index).to_rgba());
+            }
+        }
+        let warnings = unknown.into_iter()
+            .map(|name| Problem::warning(format!("'{}' is not in the tileset, skipped", name)))
+            .collect();
+        Ok((image, warnings))
+    }
+}
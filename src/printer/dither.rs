@@ -0,0 +1,499 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use image::{Pixel, Rgb, Rgba, RgbaImage};
+use rayon::prelude::*;
+use crate::printer::{IndexedImage, PrinterError, Tileset};
+use crate::printer::color::{linear_to_srgb, srgb_to_linear_table};
+use crate::printer::builder::check_size;
+use crate::printer::indexed::IndexedRow;
+use crate::PrinterResult;
+
+/// How far ordered and noise thresholds may push a channel at full strength
+const ORDERED_SPREAD: f32 = 64.0;
+
+const BLUE_NOISE_SIDE: usize = 64;
+const BLUE_NOISE_SIGMA: f32 = 1.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DitherMode {
+    None,
+    FloydSteinberg,
+    Atkinson,
+    JarvisJudiceNinke,
+    Stucki,
+    Sierra,
+    Burkes,
+    Bayer2,
+    Bayer4,
+    Bayer8,
+    BlueNoise,
+}
+
+const DITHER_MODES: [(&str, DitherMode); 11] = [
+    ("none", DitherMode::None),
+    ("floyd-steinberg", DitherMode::FloydSteinberg),
+    ("atkinson", DitherMode::Atkinson),
+    ("jarvis", DitherMode::JarvisJudiceNinke),
+    ("stucki", DitherMode::Stucki),
+    ("sierra", DitherMode::Sierra),
+    ("burkes", DitherMode::Burkes),
+    ("bayer2", DitherMode::Bayer2),
+    ("bayer4", DitherMode::Bayer4),
+    ("bayer8", DitherMode::Bayer8),
+    ("blue-noise", DitherMode::BlueNoise),
+];
+
+/// Error diffusion taps as (dx, dy, weight), weights are divided by divisor
+struct Kernel {
+    divisor: f32,
+    taps: &'static [(i32, i32, f32)],
+}
+
+const KERNEL_FLOYD_STEINBERG: Kernel = Kernel {
+    divisor: 16.0,
+    taps: &[(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)],
+};
+
+// only 6/8 of the error is spread, this is intended
+const KERNEL_ATKINSON: Kernel = Kernel {
+    divisor: 8.0,
+    taps: &[
+        (1, 0, 1.0), (2, 0, 1.0),
+        (-1, 1, 1.0), (0, 1, 1.0), (1, 1, 1.0),
+        (0, 2, 1.0),
+    ],
+};
+
+const KERNEL_JARVIS_JUDICE_NINKE: Kernel = Kernel {
+    divisor: 48.0,
+    taps: &[
+        (1, 0, 7.0), (2, 0, 5.0),
+        (-2, 1, 3.0), (-1, 1, 5.0), (0, 1, 7.0), (1, 1, 5.0), (2, 1, 3.0),
+        (-2, 2, 1.0), (-1, 2, 3.0), (0, 2, 5.0), (1, 2, 3.0), (2, 2, 1.0),
+    ],
+};
+
+const KERNEL_STUCKI: Kernel = Kernel {
+    divisor: 42.0,
+    taps: &[
+        (1, 0, 8.0), (2, 0, 4.0),
+        (-2, 1, 2.0), (-1, 1, 4.0), (0, 1, 8.0), (1, 1, 4.0), (2, 1, 2.0),
+        (-2, 2, 1.0), (-1, 2, 2.0), (0, 2, 4.0), (1, 2, 2.0), (2, 2, 1.0),
+    ],
+};
+
+const KERNEL_SIERRA: Kernel = Kernel {
+    divisor: 32.0,
+    taps: &[
+        (1, 0, 5.0), (2, 0, 3.0),
+        (-2, 1, 2.0), (-1, 1, 4.0), (0, 1, 5.0), (1, 1, 4.0), (2, 1, 2.0),
+        (-1, 2, 2.0), (0, 2, 3.0), (1, 2, 2.0),
+    ],
+};
+
+const KERNEL_BURKES: Kernel = Kernel {
+    divisor: 32.0,
+    taps: &[
+        (1, 0, 8.0), (2, 0, 4.0),
+        (-2, 1, 2.0), (-1, 1, 4.0), (0, 1, 8.0), (1, 1, 4.0), (2, 1, 2.0),
+    ],
+};
+
+impl DitherMode {
+    pub fn names() -> Vec<&'static str> {
+        DITHER_MODES.iter().map(|(name, _)| *name).collect()
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        DITHER_MODES.iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, mode)| *mode)
+    }
+
+    fn kernel(&self) -> Option<&'static Kernel> {
+        match self {
+            DitherMode::FloydSteinberg => Some(&KERNEL_FLOYD_STEINBERG),
+            DitherMode::Atkinson => Some(&KERNEL_ATKINSON),
+            DitherMode::JarvisJudiceNinke => Some(&KERNEL_JARVIS_JUDICE_NINKE),
+            DitherMode::Stucki => Some(&KERNEL_STUCKI),
+            DitherMode::Sierra => Some(&KERNEL_SIERRA),
+            DitherMode::Burkes => Some(&KERNEL_BURKES),
+            _ => None,
+        }
+    }
+}
+
+/// Square threshold map with values in 0..1
+struct ThresholdMap {
+    side: usize,
+    values: Vec<f32>,
+}
+
+impl ThresholdMap {
+    fn bayer(side: usize) -> Self {
+        let mut ranks = vec![0u32];
+        let mut current = 1;
+        while current < side {
+            let next = current * 2;
+            let mut grown = vec![0u32; next * next];
+            for y in 0..current {
+                for x in 0..current {
+                    let rank = ranks[y * current + x] * 4;
+                    grown[y * next + x] = rank;
+                    grown[y * next + x + current] = rank + 2;
+                    grown[(y + current) * next + x] = rank + 3;
+                    grown[(y + current) * next + x + current] = rank + 1;
+                }
+            }
+            ranks = grown;
+            current = next;
+        }
+        Self::from_ranks(side, &ranks)
+    }
+
+    /// Void-and-cluster blue noise on a torus
+    fn blue_noise(side: usize) -> Self {
+        let count = side * side;
+        let mut kernel = vec![0f32; count];
+        for dy in 0..side {
+            for dx in 0..side {
+                let wx = dx.min(side - dx) as f32;
+                let wy = dy.min(side - dy) as f32;
+                kernel[dy * side + dx] =
+                    (-(wx * wx + wy * wy) / (2.0 * BLUE_NOISE_SIGMA * BLUE_NOISE_SIGMA)).exp();
+            }
+        }
+        let mut energy = vec![0f32; count];
+        let toggle = |energy: &mut Vec<f32>, idx: usize, sign: f32| {
+            let (px, py) = (idx % side, idx / side);
+            for y in 0..side {
+                let dy = (y + side - py) % side;
+                for x in 0..side {
+                    let dx = (x + side - px) % side;
+                    energy[y * side + x] += sign * kernel[dy * side + dx];
+                }
+            }
+        };
+        let tightest = |pattern: &[bool], energy: &[f32]| {
+            (0..count).filter(|i| pattern[*i])
+                .max_by(|a, b| energy[*a].total_cmp(&energy[*b]))
+                .expect("pattern has ones")
+        };
+        let largest_void = |pattern: &[bool], energy: &[f32]| {
+            (0..count).filter(|i| !pattern[*i])
+                .min_by(|a, b| energy[*a].total_cmp(&energy[*b]))
+                .expect("pattern has zeros")
+        };
+
+        // deterministic initial pattern, about a tenth of the pixels set
+        let mut pattern = vec![false; count];
+        let mut seed: u32 = 0x9e37_79b9;
+        let ones = count / 10;
+        let mut placed = 0;
+        while placed < ones {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            let idx = seed as usize % count;
+            if !pattern[idx] {
+                pattern[idx] = true;
+                toggle(&mut energy, idx, 1.0);
+                placed += 1;
+            }
+        }
+        loop {
+            let cluster = tightest(&pattern, &energy);
+            pattern[cluster] = false;
+            toggle(&mut energy, cluster, -1.0);
+            let void = largest_void(&pattern, &energy);
+            pattern[void] = true;
+            toggle(&mut energy, void, 1.0);
+            if void == cluster {
+                break;
+            }
+        }
+
+        let mut ranks = vec![0u32; count];
+        let prototype = pattern.clone();
+        let prototype_energy = energy.clone();
+        for rank in (0..ones).rev() {
+            let cluster = tightest(&pattern, &energy);
+            pattern[cluster] = false;
+            toggle(&mut energy, cluster, -1.0);
+            ranks[cluster] = rank as u32;
+        }
+        pattern = prototype;
+        energy = prototype_energy;
+        for rank in ones..count {
+            let void = largest_void(&pattern, &energy);
+            pattern[void] = true;
+            toggle(&mut energy, void, 1.0);
+            ranks[void] = rank as u32;
+        }
+        Self::from_ranks(side, &ranks)
+    }
+
+    fn from_ranks(side: usize, ranks: &[u32]) -> Self {
+        let count = (side * side) as f32;
+        ThresholdMap {
+            side,
+            values: ranks.iter().map(|r| (*r as f32 + 0.5) / count).collect(),
+        }
+    }
+
+    fn at(&self, x: u32, y: u32) -> f32 {
+        self.values[(y as usize % self.side) * self.side + x as usize % self.side]
+    }
+}
+
+/// Values error and thresholds are applied to: sRGB as is or linear light,
+/// both on 0..255 scale so strength means roughly the same
+struct LightSpace {
+    linear: Option<[f32; 256]>,
+}
+
+impl LightSpace {
+    fn new(linear_light: bool) -> Self {
+        LightSpace { linear: linear_light.then(|| srgb_to_linear_table().map(|v| v * 255.0)) }
+    }
+
+    fn decode(&self, value: u8) -> f32 {
+        match &self.linear {
+            Some(table) => table[value as usize],
+            None => value as f32,
+        }
+    }
+
+    fn encode(&self, rgb: [f32; 3]) -> Rgb<u8> {
+        match &self.linear {
+            Some(_) => Rgb::from(rgb.map(|v| linear_to_srgb(v as f64 / 255.0))),
+            None => Rgb::from(rgb.map(|v| v.round().clamp(0.0, 255.0) as u8)),
+        }
+    }
+}
+
+pub struct Ditherer {
+    mode: DitherMode,
+    strength: f32,
+    serpentine: bool,
+    alpha_threshold: u8,
+    linear_light: bool,
+}
+
+impl Ditherer {
+    pub fn new(mode: DitherMode) -> Self {
+        Ditherer {
+            mode,
+            strength: 1.0,
+            serpentine: false,
+            alpha_threshold: 0,
+            linear_light: false,
+        }
+    }
+
+    /// Multiplier for diffused error or threshold spread
+    pub fn strength(mut self, value: f32) -> Self {
+        self.strength = value;
+        self
+    }
+
+    /// Alternate scan direction every row, error diffusion only
+    pub fn serpentine(mut self, value: bool) -> Self {
+        self.serpentine = value;
+        self
+    }
+
+    /// Pixels with alpha below `value` are skipped,
+    /// they neither receive nor pass on diffused error
+    pub fn alpha_threshold(mut self, value: u8) -> Self {
+        self.alpha_threshold = value;
+        self
+    }
+
+    /// Diffuse error and apply thresholds on linear light values.
+    /// Pair it with `ColorMetric::linear_light` so tiles are compared the same way
+    pub fn linear_light(mut self, value: bool) -> Self {
+        self.linear_light = value;
+        self
+    }
+
+    fn is_masked(&self, pixel: &Rgba<u8>) -> bool {
+        pixel.0[3] < self.alpha_threshold
+    }
+
+    /// Picks a tileset entry for every pixel that isn't masked out.
+    /// Runs on the rayon thread pool, result doesn't depend on thread count.
+    /// Fails with `PrinterError::EmptyPalette` on an empty tileset
+    /// and with `TilesetError::TooLarge` on one with more than 65536 entries
+    pub fn dither(&self, image: &RgbaImage, tileset: &Tileset) -> PrinterResult<IndexedImage> {
+        if tileset.is_empty() {
+            return Err(PrinterError::EmptyPalette);
+        }
+        check_size(tileset.len())?;
+        Ok(match self.mode {
+            DitherMode::None => self.dither_pointwise(image, tileset, |_, _, pixel| pixel.to_rgb()),
+            DitherMode::Bayer2 => self.dither_ordered(image, tileset, &ThresholdMap::bayer(2)),
+            DitherMode::Bayer4 => self.dither_ordered(image, tileset, &ThresholdMap::bayer(4)),
+            DitherMode::Bayer8 => self.dither_ordered(image, tileset, &ThresholdMap::bayer(8)),
+            DitherMode::BlueNoise =>
+                self.dither_ordered(image, tileset, &ThresholdMap::blue_noise(BLUE_NOISE_SIDE)),
+            mode => self.dither_diffusion(image, tileset, mode.kernel().expect("error diffusion kernel")),
+        })
+    }
+
+    /// Every pixel is mapped on its own, so rows go in parallel
+    fn dither_pointwise<F>(&self, image: &RgbaImage, tileset: &Tileset, adjust: F) -> IndexedImage
+    where F: Fn(u32, u32, &Rgba<u8>) -> Rgb<u8> + Sync {
+        let (width, height) = image.dimensions();
+        let mut indexed = IndexedImage::new(width, height);
+        indexed.par_rows_mut().for_each(|(y, mut row)| {
+            for x in 0..width {
+                let pixel = image.get_pixel(x, y);
+                if self.is_masked(pixel) {
+                    continue;
+                }
+                row.set(x, tileset.map_pixel(&adjust(x, y, pixel), x, y));
+            }
+        });
+        indexed
+    }
+
+    fn dither_ordered(&self, image: &RgbaImage, tileset: &Tileset, thresholds: &ThresholdMap) -> IndexedImage {
+        let spread = ORDERED_SPREAD * self.strength;
+        let space = LightSpace::new(self.linear_light);
+        self.dither_pointwise(image, tileset, |x, y, pixel| {
+            let offset = (thresholds.at(x, y) - 0.5) * spread;
+            space.encode([
+                space.decode(pixel.0[0]) + offset,
+                space.decode(pixel.0[1]) + offset,
+                space.decode(pixel.0[2]) + offset,
+            ])
+        })
+    }
+
+    /// Rows are processed as a wavefront: a row may only touch pixels the row above
+    /// is done spreading error into. Every error cell then gets its additions in the
+    /// same order as in a plain single-threaded scan, so results are bit-identical
+    fn dither_diffusion(&self, image: &RgbaImage, tileset: &Tileset, kernel: &Kernel) -> IndexedImage {
+        let (width, height) = image.dimensions();
+        let mut indexed = IndexedImage::new(width, height);
+        let diffusion = Diffusion {
+            ditherer: self,
+            image,
+            tileset,
+            kernel,
+            space: LightSpace::new(self.linear_light),
+            reach: kernel.taps.iter().map(|(dx, _, _)| dx.unsigned_abs()).max().unwrap_or(0) as usize,
+            errors: (0..width as usize * height as usize * 3).map(|_| AtomicU32::new(0)).collect(),
+            progress: (0..height).map(|_| AtomicUsize::new(0)).collect(),
+        };
+        let threads = rayon::current_num_threads().max(1);
+        let mut buckets: Vec<Vec<_>> = (0..threads).map(|_| vec![]).collect();
+        for (y, row) in indexed.rows_mut().enumerate() {
+            buckets[y % threads].push((y as u32, row));
+        }
+        std::thread::scope(|scope| {
+            for bucket in buckets {
+                let diffusion = &diffusion;
+                scope.spawn(move || {
+                    for (y, mut row) in bucket {
+                        diffusion.run_row(y, &mut row);
+                    }
+                });
+            }
+        });
+        indexed
+    }
+}
+
+/// Shared state of a multithreaded error diffusion pass
+struct Diffusion<'a> {
+    ditherer: &'a Ditherer,
+    image: &'a RgbaImage,
+    tileset: &'a Tileset,
+    kernel: &'a Kernel,
+    space: LightSpace,
+    // widest horizontal tap
+    reach: usize,
+    // accumulated error per pixel and channel, f32 bits
+    errors: Vec<AtomicU32>,
+    // pixels finished per row, in that row's scan order
+    progress: Vec<AtomicUsize>,
+}
+
+impl Diffusion<'_> {
+    fn is_reversed(&self, y: u32) -> bool {
+        self.ditherer.serpentine && y % 2 == 1
+    }
+
+    /// Blocks until row `y - 1` is done with everything pixel `x` of row `y` touches
+    fn wait_for_previous_row(&self, y: u32, x: usize) {
+        if y == 0 {
+            return;
+        }
+        let width = self.image.width() as usize;
+        let needed = if self.is_reversed(y - 1) {
+            width - x.saturating_sub(2 * self.reach)
+        } else {
+            width.min(x + 2 * self.reach + 1)
+        };
+        let progress = &self.progress[y as usize - 1];
+        let mut spins = 0u32;
+        while progress.load(Ordering::Acquire) < needed {
+            if spins < 64 {
+                std::hint::spin_loop();
+                spins += 1;
+            } else {
+                std::thread::yield_now();
+            }
+        }
+    }
+
+    fn error(&self, idx: usize) -> f32 {
+        f32::from_bits(self.errors[idx].load(Ordering::Relaxed))
+    }
+
+    fn add_error(&self, idx: usize, value: f32) {
+        let cell = &self.errors[idx];
+        cell.store((f32::from_bits(cell.load(Ordering::Relaxed)) + value).to_bits(), Ordering::Relaxed);
+    }
+
+    fn run_row(&self, y: u32, row: &mut IndexedRow) {
+        let (width, height) = self.image.dimensions();
+        let (width, height) = (width as i32, height as i32);
+        let reverse = self.is_reversed(y);
+        let y = y as i32;
+        for step in 0..width {
+            let x = if reverse { width - 1 - step } else { step };
+            self.wait_for_previous_row(y as u32, x as usize);
+            let pixel = self.image.get_pixel(x as u32, y as u32);
+            if !self.ditherer.is_masked(pixel) {
+                let idx = (y * width + x) as usize * 3;
+                let mut rgb = [0f32; 3];
+                for (c, value) in rgb.iter_mut().enumerate() {
+                    *value = (self.space.decode(pixel.0[c]) + self.error(idx + c)).clamp(0.0, 255.0);
+                }
+                let index = self.tileset.map_pixel(&self.space.encode(rgb), x as u32, y as u32);
+                row.set(x as u32, index);
+                let mapped = self.tileset.color(index);
+
+                let mut error = [0f32; 3];
+                for c in 0..3 {
+                    error[c] = (rgb[c] - self.space.decode(mapped.0[c])) * self.ditherer.strength / self.kernel.divisor;
+                }
+                for (dx, dy, weight) in self.kernel.taps {
+                    let tx = if reverse { x - dx } else { x + dx };
+                    let ty = y + dy;
+                    if tx < 0 || tx >= width || ty >= height
+                        || self.ditherer.is_masked(self.image.get_pixel(tx as u32, ty as u32)) {
+                        continue;
+                    }
+                    let target = (ty * width + tx) as usize * 3;
+                    for (c, value) in error.iter().enumerate() {
+                        self.add_error(target + c, value * weight);
+                    }
+                }
+            }
+            self.progress[y as usize].store(step as usize + 1, Ordering::Release);
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::{BTreeMap, BTreeSet};
+use image::Rgb;
+use serde_json::Value;
+use crate::printer::{GroundSprites, NameFilter, PrinterError, Problem, TileKind};
+use crate::printer::tile::{HexColor, Tile};
+use crate::PrinterResult;
+
+/// Entity colors checked in this order, friendly one is what the map shows for own buildings
+const ENTITY_COLOR_KEYS: [&str; 3] = ["friendly_map_color", "map_color", "enemy_map_color"];
+
+fn dump_error(message: &str) -> PrinterError {
+    PrinterError::TilesetParse { message: message.to_string(), source: None }
+}
+
+/// Builds tileset entries from `script-output/data-raw-dump.json` written by `factorio --dump-data`.
+/// Tiles are tile prototypes some item places, entities are 1x1 prototypes some item places
+/// and that have a map color of their own. Entities without a collision box are skipped. Both are sorted by name, tiles first.
+/// With `ground` tiles also get ground view colors from their sprites. Whatever is skipped goes to `warnings`
+pub(super) fn tiles_from_dump(
+    data: &str,
+    filter: &NameFilter,
+    ground: Option<&GroundSprites>,
+    warnings: &mut Vec<Problem>,
+) -> PrinterResult<Vec<Tile>> {
+    let root: Value = serde_json::from_str(data)
+        .map_err(|e| PrinterError::tileset_parse("can't parse data-raw dump", e))?;
+    let dump = root.as_object().ok_or_else(|| dump_error("data-raw dump must be a JSON object"))?;
+    let mut placed_tiles = BTreeSet::new();
+    let mut placed_entities = BTreeSet::new();
+    for prototypes in dump.values().filter_map(Value::as_object) {
+        for prototype in prototypes.values() {
+            if let Some(tile) = prototype.pointer("/place_as_tile/result").and_then(Value::as_str) {
+                placed_tiles.insert(tile);
+            }
+            if let Some(entity) = prototype.get("place_result").and_then(Value::as_str) {
+                placed_entities.insert(entity);
+            }
+        }
+    }
+
+    let mut tiles = vec![];
+    let tile_prototypes = dump.get("tile").and_then(Value::as_object)
+        .ok_or_else(|| dump_error("data-raw dump has no tile prototypes"))?;
+    for name in placed_tiles.into_iter().filter(|name| filter.matches(name)) {
+        let Some(prototype) = tile_prototypes.get(name) else {
+            warnings.push(Problem::warning(format!("tile '{}' is placed by an item, but has no prototype", name)));
+            continue;
+        };
+        let Some(color) = prototype.get("map_color").and_then(parse_color) else {
+            warnings.push(Problem::warning(format!("tile '{}' has no map_color, skipped", name)));
+            continue;
+        };
+        let mut tile = Tile::new(name, TileKind::Tile, Rgb::from(color));
+        if let Some(ground) = ground {
+            tile.ground = ground.tile_color(prototype, warnings)?.map(HexColor);
+            if tile.ground.is_none() {
+                warnings.push(Problem::warning(format!("no ground sprites for tile '{}'", name)));
+            }
+        }
+        tiles.push(tile);
+    }
+
+    let mut entities = BTreeMap::new();
+    for (prototype_type, prototypes) in dump {
+        if prototype_type == "tile" {
+            continue;
+        }
+        let Some(prototypes) = prototypes.as_object() else { continue };
+        for (name, prototype) in prototypes {
+            if !placed_entities.contains(name.as_str()) || !filter.matches(name) {
+                continue;
+            }
+            let Some(color) = ENTITY_COLOR_KEYS.iter().find_map(|key| prototype.get(*key)).and_then(parse_color) else {
+                continue;
+            };
+            match is_single_cell(prototype) {
+                Some(true) => { entities.insert(name.as_str(), color); }
+                Some(false) => {}
+                None => warnings.push(Problem::warning(format!("entity '{}' has no collision_box, skipped", name))),
+            }
+        }
+    }
+    for (name, color) in entities {
+        tiles.push(Tile::new(name, TileKind::Entity, Rgb::from(color)));
+    }
+    Ok(tiles)
+}
+
+/// Factorio color as `{r, g, b, a}` or `[r, g, b, a]`, on 0..1 scale unless some component is above 1
+fn parse_color(value: &Value) -> Option<[u8; 3]> {
+    if !value.is_object() && !value.is_array() {
+        return None;
+    }
+    let component = |key: &str, idx: usize| match value {
+        Value::Object(map) => map.get(key).and_then(Value::as_f64),
+        Value::Array(items) => items.get(idx).and_then(Value::as_f64),
+        _ => None,
+    };
+    let rgba = [
+        component("r", 0).unwrap_or(0.0),
+        component("g", 1).unwrap_or(0.0),
+        component("b", 2).unwrap_or(0.0),
+        component("a", 3).unwrap_or(1.0),
+    ];
+    let scale = if rgba.iter().any(|c| *c > 1.0) { 1.0 } else { 255.0 };
+    Some([0, 1, 2].map(|c| (rgba[c] * scale).round().clamp(0.0, 255.0) as u8))
+}
+
+/// Collision box as `[[x1, y1], [x2, y2]]` or `{left_top, right_bottom}`, `None` if it's missing or malformed
+fn is_single_cell(prototype: &Value) -> Option<bool> {
+    let point = |value: Option<&Value>| -> Option<(f64, f64)> {
+        match value? {
+            Value::Array(xy) => Some((xy.first()?.as_f64()?, xy.get(1)?.as_f64()?)),
+            Value::Object(xy) => Some((xy.get("x")?.as_f64()?, xy.get("y")?.as_f64()?)),
+            _ => None,
+        }
+    };
+    let ((x1, y1), (x2, y2)) = match prototype.get("collision_box")? {
+        Value::Array(corners) => point(corners.first()).zip(point(corners.get(1)))?,
+        Value::Object(corners) => point(corners.get("left_top")).zip(point(corners.get("right_bottom")))?,
+        _ => return None,
+    };
+    let tile_width = prototype.get("tile_width").and_then(Value::as_f64);
+    let tile_height = prototype.get("tile_height").and_then(Value::as_f64);
+    Some(tile_width.unwrap_or((x2 - x1).ceil()) <= 1.0 && tile_height.unwrap_or((y2 - y1).ceil()) <= 1.0)
+}
+
+/// Names of all tile prototypes and of all other prototypes in the dump
+pub(super) fn prototype_names(data: &str) -> PrinterResult<(BTreeSet<String>, BTreeSet<String>)> {
+    let root: Value = serde_json::from_str(data)
+        .map_err(|e| PrinterError::tileset_parse("can't parse data-raw dump", e))?;
+    let dump = root.as_object().ok_or_else(|| dump_error("data-raw dump must be a JSON object"))?;
+    let mut tiles = BTreeSet::new();
+    let mut others = BTreeSet::new();
+    for (prototype_type, prototypes) in dump {
+        let Some(prototypes) = prototypes.as_object() else { continue };
+        let names = if prototype_type == "tile" { &mut tiles } else { &mut others };
+        names.extend(prototypes.keys().cloned());
+    }
+    Ok((tiles, others))
+}
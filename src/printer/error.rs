@@ -0,0 +1,137 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use image::ImageError;
+use crate::printer::TilesetError;
+
+/// Underlying error of a `PrinterError`
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Everything that can fail in this crate. `source` gives the underlying error when there is one.
+/// Each kind has its own process exit code, see `exit_code`
+#[derive(Debug)]
+pub enum PrinterError {
+    /// Bad argument value, unknown preset and the like
+    InvalidInput(String),
+    /// Reading or writing a file failed
+    Io { message: String, source: io::Error },
+    /// Image can't be decoded or encoded
+    Image { message: String, source: ImageError },
+    /// Tileset, name mapping or data-raw dump can't be parsed, or a tileset can't be written in a format
+    TilesetParse { message: String, source: Option<BoxError> },
+    /// Tileset parsed fine, but has problems like duplicate names
+    InvalidTileset { message: String, source: Option<TilesetError> },
+    /// Tileset has no entries to print with
+    EmptyPalette,
+    /// Blueprint string can't be made
+    BlueprintEncode { message: String, source: Option<BoxError> },
+    /// Blueprint string can't be read or rendered
+    BlueprintDecode { message: String, source: Option<BoxError> },
+}
+
+impl PrinterError {
+    pub fn io(message: impl Into<String>, source: io::Error) -> Self {
+        PrinterError::Io { message: message.into(), source }
+    }
+
+    pub fn image(message: impl Into<String>, source: ImageError) -> Self {
+        PrinterError::Image { message: message.into(), source }
+    }
+
+    pub fn tileset_parse(message: impl Into<String>, source: impl Into<BoxError>) -> Self {
+        PrinterError::TilesetParse { message: message.into(), source: Some(source.into()) }
+    }
+
+    pub fn blueprint_encode(source: impl Into<BoxError>) -> Self {
+        PrinterError::BlueprintEncode { message: "can't encode blueprint".to_string(), source: Some(source.into()) }
+    }
+
+    pub fn blueprint_decode(message: impl Into<String>, source: impl Into<BoxError>) -> Self {
+        PrinterError::BlueprintDecode { message: message.into(), source: Some(source.into()) }
+    }
+
+    /// Prefixes the message with the file the error came from
+    pub fn in_file(self, path: &Path) -> Self {
+        let prefix = |message: String| format!("{}: {}", path.display(), message);
+        match self {
+            PrinterError::InvalidInput(message) => PrinterError::InvalidInput(prefix(message)),
+            PrinterError::Io { message, source } => PrinterError::Io { message: prefix(message), source },
+            PrinterError::Image { message, source } => PrinterError::Image { message: prefix(message), source },
+            PrinterError::TilesetParse { message, source } =>
+                PrinterError::TilesetParse { message: prefix(message), source },
+            PrinterError::InvalidTileset { message, source } =>
+                PrinterError::InvalidTileset { message: prefix(message), source },
+            PrinterError::BlueprintEncode { message, source } =>
+                PrinterError::BlueprintEncode { message: prefix(message), source },
+            PrinterError::BlueprintDecode { message, source } =>
+                PrinterError::BlueprintDecode { message: prefix(message), source },
+            PrinterError::EmptyPalette => PrinterError::EmptyPalette,
+        }
+    }
+
+    /// Process exit code of the command line tool. 1 is left for other failures, 2 is taken by usage errors
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            PrinterError::InvalidInput(_) => 3,
+            PrinterError::Io { .. } => 4,
+            PrinterError::Image { .. } => 5,
+            PrinterError::TilesetParse { .. } => 6,
+            PrinterError::InvalidTileset { .. } => 7,
+            PrinterError::EmptyPalette => 8,
+            PrinterError::BlueprintEncode { .. } => 9,
+            PrinterError::BlueprintDecode { .. } => 10,
+        }
+    }
+}
+
+impl fmt::Display for PrinterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PrinterError::InvalidInput(message) => write!(f, "{}", message),
+            PrinterError::Io { message, .. }
+            | PrinterError::Image { message, .. }
+            | PrinterError::TilesetParse { message, .. }
+            | PrinterError::InvalidTileset { message, .. }
+            | PrinterError::BlueprintEncode { message, .. }
+            | PrinterError::BlueprintDecode { message, .. } => write!(f, "{}", message),
+            PrinterError::EmptyPalette => write!(f, "tileset has no entries to print with"),
+        }
+    }
+}
+
+impl Error for PrinterError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PrinterError::Io { source, .. } => Some(source),
+            PrinterError::Image { source, .. } => Some(source),
+            PrinterError::InvalidTileset { source, .. } => source.as_ref().map(|e| e as &(dyn Error + 'static)),
+            PrinterError::TilesetParse { source, .. }
+            | PrinterError::BlueprintEncode { source, .. }
+            | PrinterError::BlueprintDecode { source, .. } =>
+                source.as_ref().map(|e| e.as_ref() as &(dyn Error + 'static)),
+            PrinterError::InvalidInput(_) | PrinterError::EmptyPalette => None,
+        }
+    }
+}
+
+impl From<io::Error> for PrinterError {
+    fn from(e: io::Error) -> Self {
+        PrinterError::io("I/O error", e)
+    }
+}
+
+impl From<ImageError> for PrinterError {
+    fn from(e: ImageError) -> Self {
+        PrinterError::image("image error", e)
+    }
+}
+
+impl From<TilesetError> for PrinterError {
+    fn from(e: TilesetError) -> Self {
+        match e {
+            TilesetError::Empty => PrinterError::EmptyPalette,
+            e => PrinterError::InvalidTileset { message: "invalid tileset".to_string(), source: Some(e) },
+        }
+    }
+}
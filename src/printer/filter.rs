@@ -0,0 +1,30 @@
+use wildmatch::WildMatch;
+
+/// Include/exclude name patterns with `*` and `?` wildcards.
+/// A name passes if it matches any include (or there are none) and no exclude
+#[derive(Debug, Default)]
+pub struct NameFilter {
+    include: Vec<WildMatch>,
+    exclude: Vec<WildMatch>,
+}
+
+impl NameFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn include(mut self, pattern: &str) -> Self {
+        self.include.push(WildMatch::new(pattern));
+        self
+    }
+
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        self.exclude.push(WildMatch::new(pattern));
+        self
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|pattern| pattern.matches(name)))
+            && !self.exclude.iter().any(|pattern| pattern.matches(name))
+    }
+}
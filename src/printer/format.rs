@@ -0,0 +1,353 @@
+use std::fmt::Write as _;
+use std::io::Read;
+use image::Rgb;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::printer::{read_all, BoxError, PrinterError, TileKind};
+use crate::printer::tile::{Tile, TileCsv, TileV1};
+use crate::PrinterResult;
+
+/// File formats a tileset can be read from and written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TilesetFormat {
+    Csv,
+    Json,
+    Toml,
+    /// GIMP palette, names come from the name column
+    Gpl,
+    /// Paint.NET palette, colors only
+    PaintNet,
+}
+
+const TILESET_FORMATS: [(&str, TilesetFormat); 5] = [
+    ("csv", TilesetFormat::Csv),
+    ("json", TilesetFormat::Json),
+    ("toml", TilesetFormat::Toml),
+    ("gpl", TilesetFormat::Gpl),
+    ("paint-net", TilesetFormat::PaintNet),
+];
+
+const KIND_TILE: &str = "tile:";
+const KIND_ENTITY: &str = "entity:";
+
+#[derive(Deserialize)]
+struct TomlTileset<T> {
+    tiles: Vec<T>,
+}
+
+#[derive(Serialize)]
+struct TomlTilesetRef<'a> {
+    tiles: &'a [Tile],
+}
+
+impl TilesetFormat {
+    pub fn names() -> Vec<&'static str> {
+        TILESET_FORMATS.iter().map(|(name, _)| *name).collect()
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        TILESET_FORMATS.iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, format)| *format)
+    }
+
+    pub fn name(&self) -> &'static str {
+        TILESET_FORMATS.iter()
+            .find(|(_, format)| format == self)
+            .map(|(name, _)| *name)
+            .expect("every format has a name")
+    }
+
+    /// Guesses format by file extension, `.txt` is taken as a Paint.NET palette
+    pub fn from_path(path: &str) -> Option<Self> {
+        let (_, extension) = path.rsplit_once('.')?;
+        match extension.to_ascii_lowercase().as_str() {
+            "csv" => Some(TilesetFormat::Csv),
+            "json" => Some(TilesetFormat::Json),
+            "toml" => Some(TilesetFormat::Toml),
+            "gpl" => Some(TilesetFormat::Gpl),
+            "txt" => Some(TilesetFormat::PaintNet),
+            _ => None,
+        }
+    }
+
+    /// Guesses format by content, falls back to CSV
+    pub fn detect(data: &str) -> Self {
+        let trimmed = data.trim_start();
+        let first_line = trimmed.lines().next().unwrap_or("").trim();
+        if trimmed.starts_with("GIMP Palette") {
+            TilesetFormat::Gpl
+        } else if trimmed.starts_with('[') && trimmed[1..].trim_start().starts_with(['{', ']']) {
+            TilesetFormat::Json
+        } else if trimmed.lines().any(|line| line.trim() == "[[tiles]]") {
+            TilesetFormat::Toml
+        } else if first_line.starts_with(';') || parse_hex_color(first_line).is_some() {
+            TilesetFormat::PaintNet
+        } else {
+            TilesetFormat::Csv
+        }
+    }
+
+    /// Palettes may lack names and take them from `TileNames`, other formats always carry their own
+    pub(super) fn carries_names(&self) -> bool {
+        !matches!(self, TilesetFormat::Gpl | TilesetFormat::PaintNet)
+    }
+
+    /// `names` are used by palette formats only
+    pub(super) fn parse(&self, data: &str, names: Option<&TileNames>) -> PrinterResult<Vec<Tile>> {
+        let parse_error = |e: BoxError| PrinterError::tileset_parse(format!("can't parse {} tileset", self.name()), e);
+        let tiles = match self {
+            // v1 files have separate red, green and blue fields
+            TilesetFormat::Csv => {
+                let mut reader = csv::ReaderBuilder::new().from_reader(data.as_bytes());
+                let headers = reader.headers().map_err(|e| parse_error(e.into()))?.clone();
+                let v1 = headers.iter().any(|column| column == "red");
+                let mut tiles = vec![];
+                for record in reader.records() {
+                    let record = record.map_err(|e| parse_error(e.into()))?;
+                    let line = record.position().map(|position| position.line() as usize);
+                    let tile: Result<Tile, BoxError> = if v1 {
+                        record.deserialize::<TileV1>(Some(&headers)).map(Tile::from).map_err(Into::into)
+                    } else {
+                        record.deserialize::<TileCsv>(Some(&headers))
+                            .map_err(BoxError::from)
+                            .and_then(|row| Tile::try_from(row).map_err(Into::into))
+                    };
+                    let mut tile = tile.map_err(|e| PrinterError::tileset_parse(
+                        format!("can't parse tileset line {}", line.unwrap_or_default()), e
+                    ))?;
+                    tile.line = line;
+                    tiles.push(tile);
+                }
+                tiles
+            }
+            TilesetFormat::Json => {
+                let json_error = |e: serde_json::Error| parse_error(e.into());
+                let value: Value = serde_json::from_str(data).map_err(json_error)?;
+                if value.pointer("/0/red").is_some() {
+                    serde_json::from_value::<Vec<TileV1>>(value).map_err(json_error)?
+                        .into_iter().map(Tile::from).collect()
+                } else {
+                    serde_json::from_value(value).map_err(json_error)?
+                }
+            }
+            TilesetFormat::Toml => {
+                let toml_error = |e: toml::de::Error| parse_error(e.into());
+                let value: toml::Value = toml::from_str(data).map_err(toml_error)?;
+                let first = value.get("tiles").and_then(|tiles| tiles.get(0));
+                if first.and_then(|tile| tile.get("red")).is_some() {
+                    value.try_into::<TomlTileset<TileV1>>().map_err(toml_error)?
+                        .tiles.into_iter().map(Tile::from).collect()
+                } else {
+                    value.try_into::<TomlTileset<Tile>>().map_err(toml_error)?.tiles
+                }
+            }
+            TilesetFormat::Gpl => return name_palette(parse_gpl(data)?, names),
+            TilesetFormat::PaintNet => return name_palette(parse_paint_net(data)?, names),
+        };
+        Ok(tiles)
+    }
+
+    pub(super) fn format(&self, tiles: &[Tile]) -> PrinterResult<String> {
+        self.format_tiles(tiles).map_err(|e| PrinterError::TilesetParse {
+            message: format!("can't write {} tileset", self.name()),
+            source: Some(e),
+        })
+    }
+
+    fn format_tiles(&self, tiles: &[Tile]) -> Result<String, BoxError> {
+        match self {
+            TilesetFormat::Csv => write_csv(tiles),
+            TilesetFormat::Json => Ok(serde_json::to_string_pretty(tiles)?),
+            TilesetFormat::Toml => Ok(toml::to_string(&TomlTilesetRef { tiles })?),
+            TilesetFormat::Gpl => {
+                let mut out = String::from("GIMP Palette\nName: factorio-printer\nColumns: 0\n#\n");
+                for tile in tiles {
+                    let [r, g, b] = tile.color.0;
+                    writeln!(out, "{:3} {:3} {:3}\t{}", r, g, b, label(tile))?;
+                }
+                Ok(out)
+            }
+            TilesetFormat::PaintNet => {
+                // names go into comments right before their colors, Paint.NET skips them
+                let mut out = String::from(";paint.net Palette File\n");
+                for tile in tiles {
+                    let [r, g, b] = tile.color.0;
+                    writeln!(out, ";{}\nFF{:02X}{:02X}{:02X}", label(tile), r, g, b)?;
+                }
+                Ok(out)
+            }
+        }
+    }
+}
+
+/// Header is the union of fields of all rows, so optional ones
+/// only show up when some row has them. Missing values are left empty
+fn write_csv<T: Serialize>(rows: &[T]) -> Result<String, BoxError> {
+    let rows = rows.iter().map(serde_json::to_value).collect::<Result<Vec<_>, _>>()?;
+    let mut columns: Vec<&str> = vec![];
+    for fields in rows.iter().filter_map(Value::as_object) {
+        // new columns go right after the previous field of the row, keeping field order
+        let mut position = 0;
+        for key in fields.keys() {
+            match columns.iter().position(|column| column == key) {
+                Some(existing) => position = existing + 1,
+                None => {
+                    columns.insert(position, key);
+                    position += 1;
+                }
+            }
+        }
+    }
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record(&columns)?;
+    for row in &rows {
+        writer.write_record(columns.iter().map(|column| match row.get(column) {
+            Some(Value::String(value)) => value.clone(),
+            Some(Value::Array(items)) => items.iter()
+                .map(|item| item.as_str().map_or_else(|| item.to_string(), str::to_string))
+                .collect::<Vec<_>>()
+                .join(";"),
+            Some(Value::Null) | None => String::new(),
+            Some(value) => value.to_string(),
+        }))?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+/// Names for palettes that only carry colors: CSV with `name,is_tile` header,
+/// one row per palette entry in the same order
+pub struct TileNames {
+    entries: Vec<TileName>,
+}
+
+#[derive(Deserialize)]
+struct TileName {
+    name: String,
+    is_tile: bool,
+}
+
+impl TileNames {
+    pub fn read(src: Box<dyn Read>) -> PrinterResult<Self> {
+        let data = read_all(src)?;
+        let mut reader = csv::ReaderBuilder::new().from_reader(data.as_slice());
+        let entries = reader.deserialize().collect::<Result<Vec<TileName>, _>>()
+            .map_err(|e| PrinterError::tileset_parse("can't parse name mapping", e))?;
+        Ok(TileNames { entries })
+    }
+}
+
+/// Palette color with its line number and label, if the format has one
+struct PaletteEntry {
+    line: usize,
+    color: [u8; 3],
+    label: Option<String>,
+}
+
+fn label(tile: &Tile) -> String {
+    let kind = match tile.kind {
+        TileKind::Tile => KIND_TILE,
+        TileKind::Entity => KIND_ENTITY,
+    };
+    format!("{}{}", kind, tile.name)
+}
+
+/// `entity:name` is an entity, `tile:name` or plain `name` is a tile
+fn parse_label(label: &str) -> (String, TileKind) {
+    if let Some(name) = label.strip_prefix(KIND_ENTITY) {
+        (name.trim().to_string(), TileKind::Entity)
+    } else {
+        (label.strip_prefix(KIND_TILE).unwrap_or(label).trim().to_string(), TileKind::Tile)
+    }
+}
+
+fn palette_error(message: String) -> PrinterError {
+    PrinterError::TilesetParse { message, source: None }
+}
+
+fn name_palette(palette: Vec<PaletteEntry>, names: Option<&TileNames>) -> PrinterResult<Vec<Tile>> {
+    if let Some(names) = names {
+        if names.entries.len() != palette.len() {
+            return Err(palette_error(format!(
+                "name mapping has {} entries, palette has {} colors", names.entries.len(), palette.len()
+            )));
+        }
+        return Ok(palette.iter().zip(&names.entries).map(|(entry, name)| {
+            let kind = if name.is_tile { TileKind::Tile } else { TileKind::Entity };
+            let mut tile = Tile::new(&name.name, kind, Rgb::from(entry.color));
+            tile.line = Some(entry.line);
+            tile
+        }).collect());
+    }
+    palette.iter().map(|entry| {
+        match entry.label.as_deref().map(parse_label) {
+            Some((name, kind)) if !name.is_empty() => {
+                let mut tile = Tile::new(&name, kind, Rgb::from(entry.color));
+                tile.line = Some(entry.line);
+                Ok(tile)
+            }
+            _ => Err(palette_error(format!("palette color on line {} has no name, use a name mapping", entry.line))),
+        }
+    }).collect()
+}
+
+fn parse_gpl(data: &str) -> PrinterResult<Vec<PaletteEntry>> {
+    let mut lines = data.lines().enumerate();
+    match lines.next() {
+        Some((_, header)) if header.trim() == "GIMP Palette" => {}
+        _ => return Err(palette_error("GIMP palette must start with 'GIMP Palette' line".to_string())),
+    }
+    let mut palette = vec![];
+    for (idx, line) in lines {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("Name:") || line.starts_with("Columns:") {
+            continue;
+        }
+        let mut parts = line.split_whitespace();
+        let mut color = [0u8; 3];
+        for channel in color.iter_mut() {
+            *channel = parts.next()
+                .and_then(|part| part.parse().ok())
+                .ok_or_else(|| palette_error(format!("bad GIMP palette color on line {}: '{}'", idx + 1, line)))?;
+        }
+        let label = parts.collect::<Vec<_>>().join(" ");
+        palette.push(PaletteEntry { line: idx + 1, color, label: (!label.is_empty()).then_some(label) });
+    }
+    Ok(palette)
+}
+
+/// `AARRGGBB` or `RRGGBB`
+fn parse_hex_color(value: &str) -> Option<[u8; 3]> {
+    if !value.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let rgb = match value.len() {
+        8 => &value[2..],
+        6 => value,
+        _ => return None,
+    };
+    let channel = |i: usize| u8::from_str_radix(&rgb[i * 2..i * 2 + 2], 16).ok();
+    Some([channel(0)?, channel(1)?, channel(2)?])
+}
+
+fn parse_paint_net(data: &str) -> PrinterResult<Vec<PaletteEntry>> {
+    let mut palette = vec![];
+    // only a comment with a kind prefix right before a color names it
+    let mut label = None;
+    for (idx, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(comment) = line.strip_prefix(';') {
+            let comment = comment.trim();
+            label = (comment.starts_with(KIND_TILE) || comment.starts_with(KIND_ENTITY))
+                .then(|| comment.to_string());
+            continue;
+        }
+        let color = parse_hex_color(line)
+            .ok_or_else(|| palette_error(format!("bad Paint.NET palette color on line {}: '{}'", idx + 1, line)))?;
+        palette.push(PaletteEntry { line: idx + 1, color, label: label.take() });
+    }
+    Ok(palette)
+}
@@ -0,0 +1,127 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde_json::Value;
+use crate::printer::{PrinterError, Problem};
+use crate::printer::color::{linear_to_srgb, median_color, srgb_to_linear_table};
+use crate::PrinterResult;
+
+/// Sprite pixels with alpha below this are transitions and edges, not ground
+const GROUND_ALPHA_THRESHOLD: u8 = 128;
+
+/// How a tile's sprite pixels are reduced to one color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroundColor {
+    /// Mean of linear light values, what the ground looks like from a distance
+    Average,
+    /// Per-channel median, ignores cracks and decals
+    Median,
+}
+
+const GROUND_COLORS: [(&str, GroundColor); 2] = [
+    ("average", GroundColor::Average),
+    ("median", GroundColor::Median),
+];
+
+impl GroundColor {
+    pub fn names() -> Vec<&'static str> {
+        GROUND_COLORS.iter().map(|(name, _)| *name).collect()
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        GROUND_COLORS.iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, mode)| *mode)
+    }
+}
+
+/// Tile colors taken from ground sprites instead of `map_color`.
+/// Sprite paths like `__base__/graphics/...` are looked up in the roots:
+/// a game `data/` directory, a directory of unpacked mods or a single mod folder
+pub struct GroundSprites {
+    roots: Vec<PathBuf>,
+    mode: GroundColor,
+}
+
+impl GroundSprites {
+    pub fn new(mode: GroundColor) -> Self {
+        GroundSprites { roots: vec![], mode }
+    }
+
+    pub fn root<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.roots.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// `None` if the prototype references no sprites that could be found, missing ones go to `warnings`
+    pub(super) fn tile_color(&self, prototype: &Value, warnings: &mut Vec<Problem>) -> PrinterResult<Option<[u8; 3]>> {
+        let mut pixels = vec![];
+        for sprite in sprite_paths(prototype) {
+            let Some(path) = sprite.iter().find_map(|candidate| self.resolve(candidate)) else {
+                warnings.push(Problem::warning(format!("sprite '{}' not found", sprite.join("' or '"))));
+                continue;
+            };
+            let image = image::open(&path)
+                .map_err(|e| PrinterError::image(format!("can't read sprite {}", path.display()), e))?
+                .to_rgba8();
+            pixels.extend(image.pixels()
+                .filter(|pixel| pixel.0[3] >= GROUND_ALPHA_THRESHOLD)
+                .map(|pixel| [pixel.0[0], pixel.0[1], pixel.0[2]]));
+        }
+        if pixels.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(match self.mode {
+            GroundColor::Average => average(&pixels),
+            GroundColor::Median => median_color(&mut pixels),
+        }))
+    }
+
+    fn resolve(&self, sprite: &str) -> Option<PathBuf> {
+        let (module, rest) = sprite.strip_prefix("__")?.split_once("__/")?;
+        self.roots.iter().find_map(|root| {
+            let path = if mod_name(root).as_deref() == Some(module) {
+                root.join(rest)
+            } else {
+                root.join(module).join(rest)
+            };
+            path.is_file().then_some(path)
+        })
+    }
+}
+
+/// Name from `info.json` if `dir` is a mod folder
+fn mod_name(dir: &Path) -> Option<String> {
+    let info: Value = serde_json::from_str(&fs::read_to_string(dir.join("info.json")).ok()?).ok()?;
+    info.get("name")?.as_str().map(str::to_string)
+}
+
+/// Full ground texture if the tile has one, its main variants otherwise.
+/// Every sprite comes with alternatives, high resolution version first
+fn sprite_paths(prototype: &Value) -> Vec<Vec<String>> {
+    let picture = |sprite: &Value| {
+        let candidates: Vec<String> = ["/hr_version/picture", "/picture"].iter()
+            .filter_map(|pointer| sprite.pointer(pointer).and_then(Value::as_str))
+            .map(str::to_string)
+            .collect();
+        (!candidates.is_empty()).then_some(candidates)
+    };
+    if let Some(background) = prototype.pointer("/variants/material_background").and_then(picture) {
+        return vec![background];
+    }
+    match prototype.pointer("/variants/main") {
+        Some(Value::Array(variants)) => variants.iter().filter_map(picture).collect(),
+        Some(Value::Object(variants)) => variants.values().filter_map(picture).collect(),
+        _ => vec![],
+    }
+}
+
+fn average(pixels: &[[u8; 3]]) -> [u8; 3] {
+    let table = srgb_to_linear_table();
+    let mut sum = [0f64; 3];
+    for pixel in pixels {
+        for c in 0..3 {
+            sum[c] += table[pixel[c] as usize] as f64;
+        }
+    }
+    sum.map(|value| linear_to_srgb(value / pixels.len() as f64))
+}
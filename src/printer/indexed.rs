@@ -0,0 +1,68 @@
+use image::{Pixel, RgbaImage};
+use rayon::prelude::*;
+use crate::printer::Tileset;
+
+/// Most tileset entries an `IndexedImage` can refer to
+pub(crate) const MAX_ENTRIES: usize = u16::MAX as usize + 1;
+
+/// Quantization result: tileset index for every pixel, `None` for skipped ones
+pub struct IndexedImage {
+    width: u32,
+    height: u32,
+    indices: Vec<Option<u16>>,
+}
+
+impl IndexedImage {
+    pub(crate) fn new(width: u32, height: u32) -> Self {
+        IndexedImage {
+            width,
+            height,
+            indices: vec![None; (width * height) as usize],
+        }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Option<usize> {
+        self.indices[(y * self.width + x) as usize].map(|idx| idx as usize)
+    }
+
+    pub(crate) fn rows_mut(&mut self) -> impl Iterator<Item = IndexedRow<'_>> {
+        self.indices.chunks_mut(self.width as usize).map(IndexedRow)
+    }
+
+    pub(crate) fn par_rows_mut(&mut self) -> impl ParallelIterator<Item = (u32, IndexedRow<'_>)> {
+        self.indices.par_chunks_mut(self.width as usize)
+            .enumerate()
+            .map(|(y, row)| (y as u32, IndexedRow(row)))
+    }
+
+    /// (x, y, index) of every pixel that made it through quantization, row by row
+    pub fn enumerate(&self) -> impl Iterator<Item = (u32, u32, usize)> + '_ {
+        self.indices.iter().enumerate().filter_map(|(i, idx)| {
+            idx.map(|idx| (i as u32 % self.width, i as u32 / self.width, idx as usize))
+        })
+    }
+
+    /// Writes tile colors into `image`, skipped pixels and alpha are left as is
+    pub fn paint(&self, image: &mut RgbaImage, tileset: &Tileset) {
+        for (x, y, idx) in self.enumerate() {
+            let pixel = image.get_pixel_mut(x, y);
+            let alpha = pixel.0[3];
+            *pixel = tileset.color(idx).to_rgba();
+            pixel.0[3] = alpha;
+        }
+    }
+}
+
+/// One row of an `IndexedImage`, lets rows be filled from different threads
+pub(crate) struct IndexedRow<'a>(&'a mut [Option<u16>]);
+
+impl IndexedRow<'_> {
+    /// `index` is below `MAX_ENTRIES`, tilesets with more entries are rejected on load
+    pub(crate) fn set(&mut self, x: u32, index: usize) {
+        self.0[x as usize] = Some(index as u16);
+    }
+}
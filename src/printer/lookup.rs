@@ -0,0 +1,169 @@
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU16, Ordering};
+use image::Rgb;
+use crate::printer::ColorMetric;
+
+const CACHE_SIZE: usize = 1 << 24;
+/// Below this a linear scan beats the tree
+const KD_TREE_MIN_TILES: usize = 32;
+
+/// Exhaustive search, the reference every other lookup has to agree with:
+/// smallest distance wins, first in tileset order on ties. 0 when there are no colors
+pub(crate) fn nearest_exhaustive(metric: &ColorMetric, prepared: &[[f32; 3]], color: &Rgb<u8>) -> usize {
+    let target = metric.prepare(color);
+    prepared.iter().enumerate().map(|(idx, prepared)|
+        (idx, metric.distance(&target, prepared))
+    ).fold((0, f32::INFINITY), |best, (idx, distance)|
+        if distance.total_cmp(&best.1).is_lt() { (idx, distance) } else { best }
+    ).0
+}
+
+struct KdNode {
+    index: usize,
+    axis: usize,
+    left: Option<usize>,
+    right: Option<usize>,
+}
+
+/// k-d tree over prepared tile colors. Pruning needs a per-axis lower bound
+/// of the metric, see `ColorMetric::axis_bounds`
+pub(crate) struct KdTree {
+    nodes: Vec<KdNode>,
+    root: Option<usize>,
+    bounds: [f32; 3],
+}
+
+impl KdTree {
+    fn new(prepared: &[[f32; 3]], bounds: [f32; 3]) -> Self {
+        let mut tree = KdTree { nodes: vec![], root: None, bounds };
+        let mut indices: Vec<usize> = (0..prepared.len()).collect();
+        tree.root = tree.build(prepared, &mut indices);
+        tree
+    }
+
+    fn build(&mut self, prepared: &[[f32; 3]], indices: &mut [usize]) -> Option<usize> {
+        if indices.is_empty() {
+            return None;
+        }
+        // split along the axis with the widest weighted spread
+        let axis = (0..3).max_by(|a, b| {
+            let spread = |axis: usize| {
+                let values = indices.iter().map(|i| prepared[*i][axis]);
+                let min = values.clone().fold(f32::INFINITY, f32::min);
+                let max = values.fold(f32::NEG_INFINITY, f32::max);
+                (max - min) * (max - min) * self.bounds[axis]
+            };
+            spread(*a).total_cmp(&spread(*b))
+        }).expect("3 axes");
+        indices.sort_by(|a, b| prepared[*a][axis].total_cmp(&prepared[*b][axis]));
+        let median = indices.len() / 2;
+        let index = indices[median];
+        let (left, rest) = indices.split_at_mut(median);
+        let left = self.build(prepared, left);
+        let right = self.build(prepared, &mut rest[1..]);
+        self.nodes.push(KdNode { index, axis, left, right });
+        Some(self.nodes.len() - 1)
+    }
+
+    fn nearest(&self, metric: &ColorMetric, prepared: &[[f32; 3]], color: &Rgb<u8>) -> usize {
+        let target = metric.prepare(color);
+        let mut best = (f32::INFINITY, usize::MAX);
+        if let Some(root) = self.root {
+            self.search(root, metric, prepared, &target, &mut best);
+        }
+        best.1
+    }
+
+    fn search(
+        &self,
+        node: usize,
+        metric: &ColorMetric,
+        prepared: &[[f32; 3]],
+        target: &[f32; 3],
+        best: &mut (f32, usize)
+    ) {
+        let node = &self.nodes[node];
+        let point = &prepared[node.index];
+        let distance = metric.distance(target, point);
+        let closer = distance.total_cmp(&best.0)
+            .then(node.index.cmp(&best.1))
+            .is_lt();
+        if closer {
+            *best = (distance, node.index);
+        }
+
+        let diff = target[node.axis] - point[node.axis];
+        let (near, far) = if diff < 0.0 {
+            (node.left, node.right)
+        } else {
+            (node.right, node.left)
+        };
+        if let Some(near) = near {
+            self.search(near, metric, prepared, target, best);
+        }
+        if let Some(far) = far {
+            // equal bound may still hide a tile with smaller index
+            let bound = diff * diff * self.bounds[node.axis];
+            if bound.total_cmp(&best.0).is_le() {
+                self.search(far, metric, prepared, target, best);
+            }
+        }
+    }
+}
+
+/// Exhaustive search memoized per sRGB color, for metrics without axis bounds.
+/// The 32 MB table is only allocated on the first lookup, so changing a tileset stays cheap
+pub(crate) struct ColorCache {
+    // tile index + 1, 0 means not computed yet
+    entries: OnceLock<Vec<AtomicU16>>,
+}
+
+impl ColorCache {
+    fn new() -> Self {
+        ColorCache { entries: OnceLock::new() }
+    }
+
+    fn nearest(&self, metric: &ColorMetric, prepared: &[[f32; 3]], color: &Rgb<u8>) -> usize {
+        let [r, g, b] = color.0;
+        let entries = self.entries.get_or_init(|| (0..CACHE_SIZE).map(|_| AtomicU16::new(0)).collect());
+        let entry = &entries[(r as usize) << 16 | (g as usize) << 8 | b as usize];
+        match entry.load(Ordering::Relaxed) {
+            0 => {
+                let index = nearest_exhaustive(metric, prepared, color);
+                entry.store(index as u16 + 1, Ordering::Relaxed);
+                index
+            }
+            stored => stored as usize - 1,
+        }
+    }
+}
+
+/// Nearest tile search structure, built once per tileset and metric.
+/// Always gives the same answer as `nearest_exhaustive`
+pub(crate) enum NearestLookup {
+    Exhaustive,
+    KdTree(KdTree),
+    Cache(ColorCache),
+}
+
+impl NearestLookup {
+    pub(crate) fn new(metric: &ColorMetric, prepared: &[[f32; 3]]) -> Self {
+        // nothing to build for an empty tileset, `Tileset::nearest` doesn't search it
+        if prepared.is_empty() || prepared.len() >= u16::MAX as usize {
+            return NearestLookup::Exhaustive;
+        }
+        match metric.axis_bounds() {
+            Some(_) if prepared.len() < KD_TREE_MIN_TILES => NearestLookup::Exhaustive,
+            Some(bounds) => NearestLookup::KdTree(KdTree::new(prepared, bounds)),
+            None => NearestLookup::Cache(ColorCache::new()),
+        }
+    }
+
+    pub(crate) fn nearest(&self, metric: &ColorMetric, prepared: &[[f32; 3]], color: &Rgb<u8>) -> usize {
+        match self {
+            NearestLookup::Exhaustive => nearest_exhaustive(metric, prepared, color),
+            NearestLookup::KdTree(tree) => tree.nearest(metric, prepared, color),
+            NearestLookup::Cache(cache) => cache.nearest(metric, prepared, color),
+        }
+    }
+}
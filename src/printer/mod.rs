@@ -1,162 +1,402 @@
-use image::{EncodableLayout, Pixel, Rgb, Rgba, RgbaImage};
+//! Image printing and everything around it:
+//! [`Tileset`] with its formats, presets, checks and previews, [`ImagePrinter`] turning images into
+//! [`IndexedImage`]s, [`FactorioBPStringBuilder`] making blueprint strings of them
+//! and [`BlueprintRenderer`] rendering blueprint strings back into images
+
+use image::{Pixel, Rgb, Rgba};
 use image::imageops::ColorMap;
-use serde::{Deserialize, Serialize};
-use serde_json;
+use serde::{Serialize, Serializer};
+use serde::ser::{Error as _, SerializeSeq};
 use flate2::write::ZlibEncoder;
 use flate2::Compression;
 use base64::engine::general_purpose::STANDARD as B64Engine;
-use std::io::{Read, Write};
-use base64::Engine;
-use crate::printer::schema::{FactorioBlueprint, FactorioBook};
+use std::collections::HashMap;
+use std::io::{BufWriter, Read, Write};
+use base64::write::EncoderWriter;
+use rayon::prelude::*;
+use crate::printer::schema::{FactorioBlueprint, FactorioBook, FactorioBookBlueprintVecElement};
+use crate::printer::lookup::{nearest_exhaustive, NearestLookup};
 use crate::PrinterResult;
 
 mod schema;
+mod color;
+mod decode;
+mod dither;
+mod indexed;
+mod lookup;
+mod scale;
+mod format;
+mod filter;
+mod dump;
+mod ground;
+mod calibrate;
+mod tile;
+mod check;
+mod preset;
+mod builder;
+mod font;
+mod preview;
+mod pipeline;
+mod error;
+
+pub use color::{composite_background, ColorMetric};
+pub use decode::{factorio_deserialize, factorio_deserialize_within, BlueprintRenderer, MAX_BLUEPRINT_BYTES};
+pub use dither::{DitherMode, Ditherer};
+pub use indexed::IndexedImage;
+pub use scale::{ResizeFilter, Resizer, TargetSize};
+pub use format::{TileNames, TilesetFormat};
+pub use filter::NameFilter;
+pub use ground::{GroundColor, GroundSprites};
+pub use calibrate::CalibrationPattern;
+pub use tile::{ColorView, Direction, Footprint, Tile, TileKind};
+pub use check::{Problem, PrototypeNames, Severity, TilesetCheck};
+pub use preset::{Preset, PresetSource, Presets};
+pub use builder::{TilesetBuilder, TilesetError};
+pub use preview::TilesetPreview;
+pub use pipeline::{load_image, load_image_within, ImagePrinter, MAX_IMAGE_PIXELS};
+pub use error::{BoxError, PrinterError};
+
+/// Book pages made at once per worker thread while a book is written
+const PAGES_PER_THREAD: usize = 4;
+
+/// Which one of the tiles sharing the same color gets picked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TieBreak {
+    /// First in tileset order
+    #[default]
+    First,
+    /// Last in tileset order
+    Last,
+    /// Cycle through them diagonally, checkerboard for a pair
+    Alternate,
+}
 
-// const COLOR_FILTER: [i32; 3] = [11, 59, 30];
-const COLOR_FILTER: [i32; 3] = [1, 1, 1];
-
-const TILESET_BASE: [(u8, u8, u8, &str, bool); 8] = [
-    (47, 49, 41, "refined-concrete", true),
-    (115, 93, 25, "refined-hazard-concrete-left", true),
-    (82, 81, 74, "stone-path", true),
-    (58, 61, 58, "concrete", true),
-    (181, 142, 33, "hazard-concrete-left", true),
-    (0, 93, 148, "wooden-chest", false),
-    (206, 158, 66, "transport-belt", false),
-    (206, 215, 206, "stone-wall", false),
+const TIE_BREAKS: [(&str, TieBreak); 3] = [
+    ("first", TieBreak::First),
+    ("last", TieBreak::Last),
+    ("alternate", TieBreak::Alternate),
 ];
 
-const TILESET_COLOR_CODING: [(u8, u8, u8, &str, bool); 20] = [
-    (47, 49, 41, "refined-concrete", true),
-    (115, 93, 25, "refined-hazard-concrete-left", true),
-    (82, 81, 74, "stone-path", true),
-    (58, 61, 58, "concrete", true),
-    (181, 142, 33, "hazard-concrete-left", true),
-    (0, 93, 148, "wooden-chest", false),
-    (206, 158, 66, "transport-belt", false),
-    (206, 215, 206, "stone-wall", false),
-
-    (100, 0, 0, "refined-concrete-red", true),
-    (8, 97, 19, "refined-concrete-green", true),
-    (16, 70, 115, "refined-concrete-blue", true),
-    (107, 61, 16, "refined-concrete-orange", true),
-    (107, 85, 8, "refined-concrete-yellow", true),
-    (115, 49, 66, "refined-concrete-pink", true),
-    (58, 12, 82, "refined-concrete-purple", true),
-    (8, 12, 8, "refined-concrete-black", true),
-    (33, 12, 0, "refined-concrete-brown", true),
-    (33, 97, 90, "refined-concrete-cyan", true),
-    (67, 97, 16, "refined-concrete-acid", true),
-    (123, 125, 123, "refined-concrete-white", true),
-];
+impl TieBreak {
+    pub fn names() -> Vec<&'static str> {
+        TIE_BREAKS.iter().map(|(name, _)| *name).collect()
+    }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct Tile {
-    red: u8,
-    green: u8,
-    blue: u8,
-    name: String,
-    is_tile: bool
+    pub fn from_name(name: &str) -> Option<Self> {
+        TIE_BREAKS.iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, tie_break)| *tie_break)
+    }
+}
+
+/// Tiles and entities an image is printed with, and how pixel colors are matched against them
+pub struct Tileset {
+    tiles: Vec<Tile>,
+    metric: ColorMetric,
+    tie_break: TieBreak,
+    view: ColorView,
+    // tile colors converted by `metric`
+    prepared: Vec<[f32; 3]>,
+    // indices of all tiles sharing the color of each tile, in tileset order
+    same_color: Vec<Vec<usize>>,
+    lookup: NearestLookup,
+    // found while loading, until the caller takes them
+    warnings: Vec<Problem>,
 }
 
-impl Tile {
-    fn new(red: u8, green: u8, blue: u8, name: &str, is_tile: bool) -> Self {
-        Tile {
-            red,
-            green,
-            blue,
-            name: name.to_string(),
-            is_tile,
+impl Tileset {
+    fn from_tiles(tiles: Vec<Tile>) -> Self {
+        let mut tileset = Tileset {
+            tiles,
+            metric: ColorMetric::default(),
+            tie_break: TieBreak::default(),
+            view: ColorView::default(),
+            prepared: vec![],
+            same_color: vec![],
+            lookup: NearestLookup::Exhaustive,
+            warnings: vec![],
+        };
+        tileset.prepare();
+        tileset
+    }
+
+    fn prepare(&mut self) {
+        self.prepared = self.tiles.iter()
+            .map(|tile| self.metric.prepare(&tile.color(self.view)))
+            .collect();
+        let mut groups: HashMap<Rgb<u8>, Vec<usize>> = HashMap::new();
+        for (idx, tile) in self.tiles.iter().enumerate() {
+            groups.entry(tile.color(self.view)).or_default().push(idx);
         }
+        self.same_color = self.tiles.iter()
+            .map(|tile| groups[&tile.color(self.view)].clone())
+            .collect();
+        self.lookup = NearestLookup::new(&self.metric, &self.prepared);
     }
 
-    fn rgb(&self) -> Rgb<u8> {
-        Rgb::from([self.red, self.green, self.blue])
+    pub fn set_metric(&mut self, metric: ColorMetric) {
+        self.metric = metric;
+        self.prepare();
     }
 
-    fn rgba(&self) -> Rgba<u8> {
-        Rgba::from([self.red, self.green, self.blue, 255])
+    pub fn set_tie_break(&mut self, tie_break: TieBreak) {
+        self.tie_break = tie_break;
     }
-}
 
-pub struct Tileset {
-    tiles: Vec<Tile>
-}
+    pub fn set_view(&mut self, view: ColorView) {
+        self.view = view;
+        self.prepare();
+    }
 
-impl Tileset {
-    fn from_const(obj: &[(u8, u8, u8, &str, bool)]) -> Self {
-        let mut tiles = vec![];
-        for (r, g, b, name, is_tile) in obj {
-            tiles.push(Tile::new(*r,*g,*b,name,*is_tile))
+    pub fn preset_base_game() -> PrinterResult<Self> {
+        preset::builtin("base")
+    }
+
+    pub fn preset_color_coding() -> PrinterResult<Self> {
+        preset::builtin("colorcoding")
+    }
+
+    /// Writes the tileset as CSV
+    pub fn write(&self, dst: Box<dyn Write>) -> PrinterResult<()> {
+        self.write_as(dst, TilesetFormat::Csv)
+    }
+
+    pub fn write_as(&self, mut dst: Box<dyn Write>, format: TilesetFormat) -> PrinterResult<()> {
+        dst.write_all(self.to_string_as(format)?.as_bytes())
+            .map_err(|e| PrinterError::io("can't write tileset", e))?;
+        Ok(())
+    }
+
+    /// Writes the tileset as CSV into a new file at `path`
+    pub fn to_file(&self, path: &str) -> PrinterResult<()> {
+        let file = std::fs::File::create(path)
+            .map_err(|e| PrinterError::io(format!("can't create {}", path), e))?;
+        self.write(Box::new(file))
+    }
+
+    /// Reads a CSV tileset
+    pub fn read(src: Box<dyn Read>) -> PrinterResult<Self> {
+        Self::read_as(src, Some(TilesetFormat::Csv), None)
+    }
+
+    /// Reads a CSV tileset file at `path`
+    pub fn from_file(path: &str) -> PrinterResult<Self> {
+        let file = std::fs::File::open(path)
+            .map_err(|e| PrinterError::io(format!("can't open {}", path), e))?;
+        Self::read(Box::new(file)).map_err(|e| e.in_file(std::path::Path::new(path)))
+    }
+
+    /// Format is detected from content when `None`.
+    /// `names` supplies names for palette formats without them.
+    /// Fails on `TilesetCheck` errors, its warnings are kept for `take_warnings`
+    pub fn read_as(src: Box<dyn Read>, format: Option<TilesetFormat>, names: Option<&TileNames>) -> PrinterResult<Self> {
+        Self::read_unchecked(src, format, names)?.validated()
+    }
+
+    /// Same as `read_as`, but the tileset may be empty or have problems. Meant for `TilesetCheck`
+    pub fn read_unchecked(src: Box<dyn Read>, format: Option<TilesetFormat>, names: Option<&TileNames>) -> PrinterResult<Self> {
+        let data = read_text(src, "tileset")?;
+        let format = format.unwrap_or_else(|| TilesetFormat::detect(&data));
+        let mut tileset = Self::from_tiles(format.parse(&data, names)?);
+        if names.is_some() && format.carries_names() {
+            tileset.warnings.push(Problem::warning(
+                format!("{} tilesets carry their own names, name mapping is ignored", format.name())
+            ));
         }
-        Tileset { tiles }
+        Ok(tileset)
     }
 
-    pub fn preset_base_game() -> Self {
-        Self::from_const(&TILESET_BASE)
+    /// Fails on `TilesetCheck` errors, its warnings are kept for `take_warnings`
+    pub fn validated(mut self) -> PrinterResult<Self> {
+        if self.is_empty() {
+            return Err(PrinterError::EmptyPalette);
+        }
+        builder::check_size(self.len())?;
+        let problems = TilesetCheck::new().min_distance(0.0).check(&self);
+        let errors: Vec<String> = problems.iter()
+            .filter(|problem| problem.severity == Severity::Error)
+            .map(|problem| problem.to_string())
+            .collect();
+        if !errors.is_empty() {
+            return Err(PrinterError::InvalidTileset {
+                message: format!("invalid tileset\n{}", errors.join("\n")),
+                source: None,
+            });
+        }
+        self.warnings.extend(problems.into_iter().filter(|problem| problem.severity == Severity::Warning));
+        Ok(self)
     }
 
-    pub fn preset_color_coding() -> Self {
-        Self::from_const(&TILESET_COLOR_CODING)
+    /// Warnings found while loading: `TilesetCheck` ones, entries skipped in a data-raw dump and the like.
+    /// Left for the caller to show, taking them clears the list
+    pub fn take_warnings(&mut self) -> Vec<Problem> {
+        std::mem::take(&mut self.warnings)
     }
 
-    pub fn write(&self, dst: Box<dyn Write>) -> PrinterResult<()> {
-        let mut writer = csv::Writer::from_writer(dst);
-        for tile in &self.tiles {
-            writer.serialize(tile)?;
+    /// Builds a tileset from `data-raw-dump.json` written by `factorio --dump-data`,
+    /// keeping only names that pass `filter`. With `ground` tiles get ground view colors of their sprites
+    pub fn from_dump(src: Box<dyn Read>, filter: &NameFilter, ground: Option<&GroundSprites>) -> PrinterResult<Self> {
+        let data = read_text(src, "data-raw dump")?;
+        let mut warnings = vec![];
+        let tiles = dump::tiles_from_dump(&data, filter, ground, &mut warnings)?;
+        if tiles.is_empty() {
+            return Err(PrinterError::EmptyPalette);
+        }
+        let mut tileset = Self::from_tiles(tiles);
+        tileset.warnings = warnings;
+        tileset.validated()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
+    /// Replaces colors of `view` profile, one per entry in tileset order
+    pub fn set_colors(&mut self, view: ColorView, colors: &[Rgb<u8>]) -> Result<(), TilesetError> {
+        if colors.len() != self.tiles.len() {
+            return Err(TilesetError::ColorCount { expected: self.tiles.len(), got: colors.len() });
         }
+        for (tile, color) in self.tiles.iter_mut().zip(colors) {
+            tile.set_color(view, *color);
+        }
+        self.prepare();
         Ok(())
     }
 
-    pub fn to_file(&self, path: &str) -> PrinterResult<()> {
-        let mut writer = csv::Writer::from_path(path)?;
-        for tile in &self.tiles {
-            writer.serialize(tile)?;
+    /// Adds entries of `other`. One sharing a name or alias with an entry already in the tileset
+    /// replaces it in place. Fails and leaves the tileset as is if one shares names with several entries
+    pub fn merge(&mut self, other: Tileset) -> Result<(), TilesetError> {
+        let mut tiles = self.tiles.clone();
+        for tile in other.tiles {
+            let names = || std::iter::once(&tile.name).chain(&tile.aliases);
+            let mut matches = (0..tiles.len()).filter(|index| names().any(|name| tiles[*index].is_named(name)));
+            match (matches.next(), matches.next()) {
+                (None, _) => tiles.push(tile),
+                (Some(index), None) => tiles[index] = tile,
+                (Some(_), Some(second)) => {
+                    let name = names().find(|name| tiles[second].is_named(name)).unwrap_or(&tile.name);
+                    return Err(TilesetError::DuplicateName(name.clone()));
+                }
+            }
         }
+        builder::check_size(tiles.len())?;
+        self.tiles = tiles;
+        self.prepare();
         Ok(())
     }
 
-    pub fn read(src: Box<dyn Read>) -> PrinterResult<Self> {
-        let mut tiles = vec![];
-        let data = read_all(src)?;
-        let mut reader = csv::ReaderBuilder::new()
-            .from_reader(data.as_bytes());
-        for row in reader.deserialize() {
-            let tile: Tile = row?;
-            tiles.push(tile);
+    /// Keeps only entries whose name passes `filter`. Fails and leaves the tileset as is if none do
+    pub fn retain_names(&mut self, filter: &NameFilter) -> Result<(), TilesetError> {
+        self.retain(|tile| filter.matches(&tile.name))
+    }
+
+    /// Keeps only tiles or only entities. Fails and leaves the tileset as is if there are none
+    pub fn retain_kind(&mut self, kind: TileKind) -> Result<(), TilesetError> {
+        self.retain(|tile| tile.kind == kind)
+    }
+
+    fn retain<F: Fn(&Tile) -> bool>(&mut self, keep: F) -> Result<(), TilesetError> {
+        if !self.tiles.iter().any(&keep) {
+            return Err(TilesetError::Empty);
         }
-        Ok(Tileset { tiles })
+        self.tiles.retain(keep);
+        self.prepare();
+        Ok(())
     }
 
-    pub fn from_file(path: &str) -> PrinterResult<Self> {
-        let mut tiles = vec![];
-        let mut reader = csv::Reader::from_path(path)?;
-        for row in reader.deserialize() {
-            let tile: Tile = row?;
-            tiles.push(tile);
+    /// Replaces `view` color of the entry with this name or alias
+    pub fn set_color(&mut self, view: ColorView, name: &str, color: Rgb<u8>) -> Result<(), TilesetError> {
+        let index = self.find_by_name(name)
+            .ok_or_else(|| TilesetError::UnknownName(name.to_string()))?;
+        self.tiles[index].set_color(view, color);
+        self.prepare();
+        Ok(())
+    }
+
+    /// Appends an entry, its name and aliases must not be in the tileset yet.
+    /// Fails with `TilesetError::TooLarge` past 65536 entries.
+    /// Like every other change it rebuilds the color lookup, build large tilesets with `TilesetBuilder` instead
+    pub fn add(&mut self, tile: Tile) -> Result<(), TilesetError> {
+        if let Some(name) = builder::taken_name(&self.tiles, &tile) {
+            return Err(TilesetError::DuplicateName(name.to_string()));
+        }
+        builder::check_size(self.tiles.len() + 1)?;
+        self.tiles.push(tile);
+        self.prepare();
+        Ok(())
+    }
+
+    /// Removes the entry with this name or alias. The last one can't be removed.
+    /// Rebuilds the color lookup, same as `add`
+    pub fn remove(&mut self, name: &str) -> Result<Tile, TilesetError> {
+        let index = self.find_by_name(name)
+            .ok_or_else(|| TilesetError::UnknownName(name.to_string()))?;
+        if self.tiles.len() == 1 {
+            return Err(TilesetError::Empty);
         }
-        Ok(Tileset { tiles })
+        let tile = self.tiles.remove(index);
+        self.prepare();
+        Ok(tile)
+    }
+
+    /// Entry with this name or alias
+    pub fn get(&self, name: &str) -> Option<&Tile> {
+        self.find_by_name(name).map(|index| &self.tiles[index])
     }
 
-    fn get_matching_tile(&self, color: &Rgb<u8>) -> &Tile {
+    /// Entries in tileset order, indices match `nearest`
+    pub fn iter(&self) -> std::slice::Iter<'_, Tile> {
         self.tiles.iter()
-            .find(|tile| tile.rgb() == *color)
-            .expect("No matching tiles. Did you forget the dithering?")
-    }
-
-    fn find_closest_color(&self, color: &Rgb<u8>) -> Rgb<u8> {
-        let index = self.tiles.iter().enumerate().map(|(idx, tile)|
-            (idx,
-            (color.0[0] as i32 - tile.red as i32)
-                * (color.0[0] as i32 - tile.red as i32) * COLOR_FILTER[0]
-                + (color.0[1] as i32 - tile.green as i32)
-                * (color.0[1] as i32 - tile.green as i32) * COLOR_FILTER[1]
-                + (color.0[2] as i32 - tile.blue as i32)
-                * (color.0[2] as i32 - tile.blue as i32) * COLOR_FILTER[2])
-        ).min_by(|(_, a), (_, b)|
-            a.cmp(b)
-        ).map(|(idx, _)| idx).expect("Tileset must not be empty");
-        self.tiles[index].rgb()
+    }
+
+    /// Tileset in `format`, same as `write_as` writes
+    pub fn to_string_as(&self, format: TilesetFormat) -> PrinterResult<String> {
+        format.format(&self.tiles)
+    }
+
+    /// Index of the entry with this name or alias
+    fn find_by_name(&self, name: &str) -> Option<usize> {
+        self.tiles.iter().position(|tile| tile.is_named(name))
+    }
+
+    /// Color of the entry in the current view
+    pub(crate) fn color(&self, index: usize) -> Rgb<u8> {
+        self.tiles[index].color(self.view)
+    }
+
+    /// First of the closest tiles in tileset order, `None` for an empty tileset
+    pub fn nearest(&self, color: &Rgb<u8>) -> Option<usize> {
+        (!self.is_empty()).then(|| self.lookup.nearest(&self.metric, &self.prepared, color))
+    }
+
+    /// Same as `nearest`, but compares against every tile. Slow, meant as a reference
+    pub fn nearest_exhaustive(&self, color: &Rgb<u8>) -> Option<usize> {
+        (!self.is_empty()).then(|| nearest_exhaustive(&self.metric, &self.prepared, color))
+    }
+
+    /// Closest tile for a pixel at (x, y), resolving same colored tiles with `tie_break`.
+    /// The tileset must not be empty, `Ditherer::dither` checks it
+    pub(crate) fn map_pixel(&self, color: &Rgb<u8>, x: u32, y: u32) -> usize {
+        let index = self.lookup.nearest(&self.metric, &self.prepared, color);
+        let candidates = &self.same_color[index];
+        match self.tie_break {
+            TieBreak::First => index,
+            TieBreak::Last => candidates[candidates.len() - 1],
+            TieBreak::Alternate => candidates[(x + y) as usize % candidates.len()],
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Tileset {
+    type Item = &'a Tile;
+    type IntoIter = std::slice::Iter<'a, Tile>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
     }
 }
 
@@ -164,26 +404,55 @@ impl ColorMap for Tileset {
     type Color = Rgba<u8>;
 
     fn index_of(&self, color: &Self::Color) -> usize {
-        for (i, tile) in self.tiles.iter().enumerate() {
-            if *color == tile.rgba() {
-                return i;
-            }
+        match self.nearest(&color.to_rgb()) {
+            Some(index) if self.color(index).to_rgba() == *color => index,
+            _ => usize::MAX,
         }
-        return usize::MAX;
     }
 
+    /// Colors stay as they are with an empty tileset
     fn map_color(&self, color: &mut Self::Color) {
-        *color = self.find_closest_color(&color.to_rgb()).to_rgba();
+        if let Some(index) = self.nearest(&color.to_rgb()) {
+            *color = self.color(index).to_rgba();
+        }
     }
 }
 
+/// Order of tiles and entities inside a blueprint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TileOrder {
+    /// Row by row, as they appear in the image
+    #[default]
+    Scan,
+    /// Grouped by tileset entry, then row by row. Compresses better
+    Grouped,
+}
+
+const TILE_ORDERS: [(&str, TileOrder); 2] = [
+    ("scan", TileOrder::Scan),
+    ("grouped", TileOrder::Grouped),
+];
+
+impl TileOrder {
+    pub fn names() -> Vec<&'static str> {
+        TILE_ORDERS.iter().map(|(name, _)| *name).collect()
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        TILE_ORDERS.iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, order)| *order)
+    }
+}
+
+/// Blueprint string of an `IndexedImage`, a single blueprint or a book of `split` squares
 pub struct FactorioBPStringBuilder<'a> {
     label: String,
-    image: &'a RgbaImage,
-    alpha: &'a RgbaImage,
+    image: &'a IndexedImage,
     tileset: &'a Tileset,
 
-    alpha_threshold: u8,
+    compression: u32,
+    tile_order: TileOrder,
     split: i32,
     split_count_x: i32,
     split_count_y: i32,
@@ -192,24 +461,29 @@ pub struct FactorioBPStringBuilder<'a> {
 impl FactorioBPStringBuilder<'_> {
     pub fn new<'a>(
         label: &str,
-        image: &'a RgbaImage,
-        alpha: &'a RgbaImage,
+        image: &'a IndexedImage,
         tileset: &'a Tileset
     ) -> FactorioBPStringBuilder<'a> {
         FactorioBPStringBuilder {
             label: label.to_string(),
             image,
-            alpha,
             tileset,
-            alpha_threshold: 128,
+            compression: Compression::default().level(),
+            tile_order: TileOrder::default(),
             split: 0,
             split_count_x: 1,
             split_count_y: 1,
         }
     }
 
-    pub fn alpha_threshold(mut self, value: u8) -> Self {
-        self.alpha_threshold = value;
+    /// zlib level, 0 (none) to 9 (best)
+    pub fn compression(mut self, value: u32) -> Self {
+        self.compression = value;
+        self
+    }
+
+    pub fn tile_order(mut self, value: TileOrder) -> Self {
+        self.tile_order = value;
         self
     }
 
@@ -243,76 +517,132 @@ impl FactorioBPStringBuilder<'_> {
         self.split_count_x * self.split_count_y
     }
 
-    fn make_blueprints(&self) -> Vec<FactorioBlueprint> {
-        let mut prints = vec![];
-        let mut icons = true;
-        for i in 0..self.get_total_bp_count() {
-            prints.push(FactorioBlueprint::new());
-            let x = i % self.split_count_x;
-            let y = i / self.split_count_x;
-            prints[i as usize].set_label(format!("{}: x: {} y: {}", &self.label, x, y));
-            if icons {
-                if x > 99 || y > 99 {
-                    icons = false;
-                } else {
-                    prints[i as usize].set_icons(x * 100 + y);
+    /// Icons of split blueprints show their position when it fits into four digits, otherwise they are 0
+    pub fn has_position_icons(&self) -> bool {
+        self.split_count_x <= 100 && self.split_count_y <= 100
+    }
+
+    /// Blueprint of split `i`, counted row by row
+    fn make_blueprint(&self, i: i32) -> PrinterResult<FactorioBlueprint> {
+        let x = i % self.split_count_x;
+        let y = i / self.split_count_x;
+        let mut print = FactorioBlueprint::new();
+        print.set_label(format!("{}: x: {} y: {}", &self.label, x, y));
+        print.set_icons(if self.has_position_icons() { x * 100 + y } else { 0 })?;
+        if self.split > 0 {
+            // lets the pages be lined up in game, and rendered back at the right offsets
+            print.set_snap_to_grid(self.split);
+        }
+        self.fill_blueprint(&mut print, x, y);
+        Ok(print)
+    }
+
+    /// Adds pixels of split (x, y) in the same row by row order as a full scan would
+    fn fill_blueprint(&self, print: &mut FactorioBlueprint, split_x: i32, split_y: i32) {
+        let (width, height) = self.image.dimensions();
+        let (x_range, y_range) = if self.split == 0 {
+            (0..width, 0..height)
+        } else {
+            let side = self.split as u32;
+            (
+                split_x as u32 * side..((split_x as u32 + 1) * side).min(width),
+                split_y as u32 * side..((split_y as u32 + 1) * side).min(height),
+            )
+        };
+        let mut pixels = vec![];
+        for y1 in y_range {
+            for x1 in x_range.clone() {
+                if let Some(index) = self.image.get(x1, y1) {
+                    pixels.push((index, x1, y1));
                 }
             }
         }
-        if !icons {
-            eprintln!("warning: resulting split side count >99, icons will be set to 0");
-            for print in &mut prints {
-                print.set_icons(0);
-            }
+        if self.tile_order == TileOrder::Grouped {
+            // stable, so row by row order is kept within a group
+            pixels.sort_by_key(|(index, _, _)| *index);
         }
-        for (
-            (x1, y1, pix1),
-            (_x2, _y2, pix2)
-        ) in self.image.enumerate_pixels().zip(self.alpha.enumerate_pixels()) {
-            if pix2.0[3] < self.alpha_threshold {
-                continue;
-            }
-            let tile = self.tileset.get_matching_tile(&pix1.to_rgb());
-            let (idx, x, y) = self.get_bp_split_coords(x1 as i32, y1 as i32);
-            // println!("pixel {},{} goes to idx({}) x({}) y({})", x1,y1, idx,x,y);
-            if tile.is_tile {
-                prints[idx].add_tile(&tile.name, x, y);
-            } else {
-                prints[idx].add_entity(&tile.name, x, y);
+        for (index, x1, y1) in pixels {
+            let tile = &self.tileset.tiles[index];
+            let (_, x, y) = self.get_bp_split_coords(x1 as i32, y1 as i32);
+            match tile.kind {
+                TileKind::Tile => print.add_tile(&tile.name, x, y),
+                TileKind::Entity => print.add_entity(&tile.name, x, y),
             }
         }
-        prints
     }
 
-    fn make_book(&self) -> FactorioBook {
-        let mut book = FactorioBook::new();
-        book.set_label(format!("{}", &self.label));
-        for bp in self.make_blueprints() {
-            book.add_blueprint(bp)
+    /// Streams the blueprint string into `dst`. Pages of a book are made and serialized
+    /// in parallel a batch at a time, everything goes straight through zlib and base64
+    pub fn factorio_serialize_to<W: Write>(&self, dst: W) -> PrinterResult<()> {
+        if self.get_total_bp_count() == 1 {
+            return factorio_encode_to(&self.make_blueprint(0)?, dst, self.compression);
         }
-        book
+        let book = FactorioBook::with_blueprints(self.label.clone(), BookPages(self));
+        factorio_encode_to(&book, dst, self.compression)
     }
 
     pub fn factorio_serialize(&self) -> PrinterResult<String> {
-        let json_std = if self.split_count_x == 1 {
-            serde_json::to_string(&self.make_blueprints()[0])?
-        } else {
-            serde_json::to_string(&self.make_book())?
-        };
-        // println!("{}", &json_std);
-        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-        encoder.write_all(json_std.as_bytes())?;
-        let compr = encoder.finish()?;
-        let out = B64Engine.encode(compr);
-        Ok(format!("0{}", out))
+        let mut out = vec![];
+        self.factorio_serialize_to(&mut out)?;
+        String::from_utf8(out).map_err(PrinterError::blueprint_encode)
+    }
+}
+
+/// Slots of a split book, only a batch of pages is held in memory while it's written
+struct BookPages<'a, 'b>(&'a FactorioBPStringBuilder<'b>);
+
+impl Serialize for BookPages<'_, '_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let builder = self.0;
+        let count = builder.get_total_bp_count();
+        let batch = (rayon::current_num_threads() * PAGES_PER_THREAD) as i32;
+        let mut seq = serializer.serialize_seq(Some(count as usize))?;
+        for start in (0..count).step_by(batch as usize) {
+            let pages = (start..(start + batch).min(count)).into_par_iter()
+                .map(|i| {
+                    let slot = FactorioBookBlueprintVecElement::blueprint(i, builder.make_blueprint(i)?);
+                    serde_json::value::to_raw_value(&slot).map_err(PrinterError::blueprint_encode)
+                })
+                .collect::<PrinterResult<Vec<_>>>()
+                .map_err(S::Error::custom)?;
+            for page in pages {
+                seq.serialize_element(&page)?;
+            }
+        }
+        seq.end()
     }
 }
 
+/// Encodes anything blueprint-like the way the game does:
+/// version byte, then base64 of zlib compressed JSON
+pub fn factorio_encode<T: Serialize>(value: &T) -> PrinterResult<String> {
+    let mut out = vec![];
+    factorio_encode_to(value, &mut out, Compression::default().level())?;
+    String::from_utf8(out).map_err(PrinterError::blueprint_encode)
+}
+
+/// Streaming version of `factorio_encode` with zlib level 0..=9
+pub fn factorio_encode_to<T: Serialize, W: Write>(value: &T, mut dst: W, compression: u32) -> PrinterResult<()> {
+    let write_error = |e| PrinterError::io("can't write blueprint string", e);
+    dst.write_all(b"0").map_err(write_error)?;
+    let b64 = EncoderWriter::new(dst, &B64Engine);
+    // serde writes JSON in tiny pieces, zlib is better fed in larger ones
+    let mut json = BufWriter::new(ZlibEncoder::new(b64, Compression::new(compression)));
+    serde_json::to_writer(&mut json, value).map_err(|e| match e.is_io() {
+        true => write_error(e.into()),
+        false => PrinterError::blueprint_encode(e),
+    })?;
+    let encoder = json.into_inner().map_err(|e| write_error(e.into_error()))?;
+    encoder.finish().map_err(write_error)?.finish().map_err(write_error)?;
+    Ok(())
+}
+
+/// Reads `source` to the end
 pub fn read_all(mut source: Box<dyn Read>) -> PrinterResult<Vec<u8>> {
     let mut out = vec![];
     let mut buf = [0u8; 2048];
     loop {
-        let bytes_read = source.read(&mut buf)?;
+        let bytes_read = source.read(&mut buf).map_err(|e| PrinterError::io("can't read input", e))?;
         out.extend_from_slice(&buf[0..bytes_read]);
         if bytes_read == 0 {
             break;
@@ -321,6 +651,12 @@ pub fn read_all(mut source: Box<dyn Read>) -> PrinterResult<Vec<u8>> {
     Ok(out)
 }
 
+/// Reads text of a tileset or data dump, `what` names it in the error
+pub(crate) fn read_text(source: Box<dyn Read>, what: &str) -> PrinterResult<String> {
+    String::from_utf8(read_all(source)?)
+        .map_err(|e| PrinterError::tileset_parse(format!("{} is not valid UTF-8", what), e))
+}
+
 
 
 
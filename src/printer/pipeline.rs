@@ -0,0 +1,163 @@
+use std::io::Cursor;
+use image::io::{Limits, Reader};
+use image::{ImageFormat, Rgb, RgbaImage};
+use crate::printer::{composite_background, DitherMode, Ditherer, IndexedImage, PrinterError, ResizeFilter, Resizer, TargetSize, Tileset};
+use crate::PrinterResult;
+
+/// Largest image `load_image` decodes and `ImagePrinter` prints by default, 8192x8192 or the same area
+pub const MAX_IMAGE_PIXELS: u64 = 1 << 26;
+
+/// Decodes an image file of any format `image` knows, the format is kept to write the preview back.
+/// Fails on images over `MAX_IMAGE_PIXELS`
+pub fn load_image(data: &[u8]) -> PrinterResult<(RgbaImage, ImageFormat)> {
+    load_image_within(data, MAX_IMAGE_PIXELS)
+}
+
+/// `load_image` for images of at most `max_pixels`, checked on the header before anything is decoded
+pub fn load_image_within(data: &[u8], max_pixels: u64) -> PrinterResult<(RgbaImage, ImageFormat)> {
+    let format = image::guess_format(data).map_err(|e| PrinterError::image("unknown image format", e))?;
+    let reader = || {
+        let side = u32::try_from(max_pixels).unwrap_or(u32::MAX);
+        let mut limits = Limits::default();
+        limits.max_image_width = Some(side);
+        limits.max_image_height = Some(side);
+        // decoded image and its RGBA copy
+        limits.max_alloc = Some(max_pixels.saturating_mul(8));
+        let mut reader = Reader::with_format(Cursor::new(data), format);
+        reader.limits(limits);
+        reader
+    };
+    let (width, height) = reader().into_dimensions()
+        .map_err(|e| PrinterError::image("can't decode image", e))?;
+    check_pixels("image", width, height, max_pixels)?;
+    let image = reader().decode()
+        .map_err(|e| PrinterError::image("can't decode image", e))?
+        .to_rgba8();
+    Ok((image, format))
+}
+
+fn check_pixels(what: &str, width: u32, height: u32, max_pixels: u64) -> PrinterResult<()> {
+    if width as u64 * height as u64 > max_pixels {
+        return Err(PrinterError::InvalidInput(
+            format!("{} would be {}x{}, at most {} pixels are supported", what, width, height, max_pixels)
+        ));
+    }
+    Ok(())
+}
+
+/// Image to tileset indices: resize, blend over background, then dither.
+/// Defaults match the command line ones. The tileset metric isn't touched,
+/// with `linear_light` set it as `ColorMetric::linear_light` yourself
+pub struct ImagePrinter<'a> {
+    tileset: &'a Tileset,
+    size: TargetSize,
+    filter: ResizeFilter,
+    linear_light: bool,
+    background: Option<Rgb<u8>>,
+    dither: DitherMode,
+    dither_strength: f32,
+    serpentine: bool,
+    alpha_threshold: u8,
+    max_pixels: u64,
+}
+
+impl<'a> ImagePrinter<'a> {
+    pub fn new(tileset: &'a Tileset) -> Self {
+        ImagePrinter {
+            tileset,
+            size: TargetSize::Scale(1.0),
+            filter: ResizeFilter::CatmullRom,
+            linear_light: false,
+            background: None,
+            dither: DitherMode::FloydSteinberg,
+            dither_strength: 1.0,
+            serpentine: false,
+            alpha_threshold: 128,
+            max_pixels: MAX_IMAGE_PIXELS,
+        }
+    }
+
+    pub fn size(mut self, value: TargetSize) -> Self {
+        self.size = value;
+        self
+    }
+
+    pub fn filter(mut self, value: ResizeFilter) -> Self {
+        self.filter = value;
+        self
+    }
+
+    /// Resize, blend and dither on linear light values
+    pub fn linear_light(mut self, value: bool) -> Self {
+        self.linear_light = value;
+        self
+    }
+
+    /// Semi-transparent pixels are blended over this color, alpha is kept
+    pub fn background(mut self, value: Rgb<u8>) -> Self {
+        self.background = Some(value);
+        self
+    }
+
+    pub fn dither(mut self, value: DitherMode) -> Self {
+        self.dither = value;
+        self
+    }
+
+    /// See `Ditherer::strength`
+    pub fn dither_strength(mut self, value: f32) -> Self {
+        self.dither_strength = value;
+        self
+    }
+
+    /// See `Ditherer::serpentine`
+    pub fn serpentine(mut self, value: bool) -> Self {
+        self.serpentine = value;
+        self
+    }
+
+    /// Pixels with alpha below `value` get no tile
+    pub fn alpha_threshold(mut self, value: u8) -> Self {
+        self.alpha_threshold = value;
+        self
+    }
+
+    /// `print` fails when the resized image would have more pixels, `MAX_IMAGE_PIXELS` by default
+    pub fn max_pixels(mut self, value: u64) -> Self {
+        self.max_pixels = value;
+        self
+    }
+
+    /// Resized image blended over the background, what `quantize` works on
+    pub fn preprocess(&self, image: RgbaImage) -> RgbaImage {
+        let mut image = Resizer::new(self.size)
+            .filter(self.filter)
+            .linear_light(self.linear_light)
+            .resize(image);
+        if let Some(background) = self.background {
+            composite_background(&mut image, background, self.linear_light);
+        }
+        image
+    }
+
+    /// Tileset entry for every pixel of a preprocessed image.
+    /// Fails with `PrinterError::EmptyPalette` on an empty tileset
+    pub fn quantize(&self, image: &RgbaImage) -> PrinterResult<IndexedImage> {
+        Ditherer::new(self.dither)
+            .strength(self.dither_strength)
+            .serpentine(self.serpentine)
+            .alpha_threshold(self.alpha_threshold)
+            .linear_light(self.linear_light)
+            .dither(image, self.tileset)
+    }
+
+    /// `preprocess` and `quantize` in one go. The preprocessed image is returned too,
+    /// `IndexedImage::paint` turns it into a preview
+    pub fn print(&self, image: RgbaImage) -> PrinterResult<(RgbaImage, IndexedImage)> {
+        let (width, height) = Resizer::new(self.size).target_dimensions(image.width(), image.height());
+        check_pixels("print", width, height, self.max_pixels)?;
+        let image = self.preprocess(image);
+        let indexed = self.quantize(&image)?;
+        Ok((image, indexed))
+    }
+}
@@ -0,0 +1,132 @@
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use crate::printer::{PrinterError, Tileset, TilesetFormat};
+use crate::PrinterResult;
+
+/// Presets compiled into the binary, CSV tilesets from `presets/`
+const BUILTIN_PRESETS: [(&str, &str); 4] = [
+    ("base", include_str!("presets/base.csv")),
+    ("base-2.0", include_str!("presets/base-2.0.csv")),
+    ("colorcoding", include_str!("presets/colorcoding.csv")),
+    ("space-age", include_str!("presets/space-age.csv")),
+];
+
+const PRESET_EXTENSION: &str = "csv";
+
+/// Where a preset comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PresetSource {
+    Builtin,
+    File(PathBuf),
+}
+
+#[derive(Debug, Clone)]
+pub struct Preset {
+    pub name: String,
+    pub source: PresetSource,
+}
+
+/// Built-in presets and `*.csv` tilesets of preset directories, named after the file.
+/// Directory presets shadow built-ins, earlier directories shadow later ones
+#[derive(Debug, Default)]
+pub struct Presets {
+    dirs: Vec<PathBuf>,
+}
+
+impl Presets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Missing directories are fine, they just have no presets
+    pub fn dir<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.dirs.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// `$XDG_CONFIG_HOME/factorio-printer/presets`, with `~/.config` when it's not set
+    pub fn user_dir() -> Option<PathBuf> {
+        let config = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(config.join("factorio-printer").join("presets"))
+    }
+
+    pub fn builtin_names() -> Vec<&'static str> {
+        BUILTIN_PRESETS.iter().map(|(name, _)| *name).collect()
+    }
+
+    /// All presets sorted by name, shadowed ones left out
+    pub fn list(&self) -> PrinterResult<Vec<Preset>> {
+        let mut presets: Vec<Preset> = vec![];
+        for dir in &self.dirs {
+            for path in preset_files(dir)? {
+                // a name that isn't valid unicode still gets listed, so it can be renamed
+                let Some(name) = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()) else {
+                    continue;
+                };
+                if presets.iter().all(|preset| preset.name != name) {
+                    presets.push(Preset { name, source: PresetSource::File(path) });
+                }
+            }
+        }
+        for name in Self::builtin_names() {
+            if presets.iter().all(|preset| preset.name != name) {
+                presets.push(Preset { name: name.to_string(), source: PresetSource::Builtin });
+            }
+        }
+        presets.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(presets)
+    }
+
+    pub fn load(&self, name: &str) -> PrinterResult<Tileset> {
+        let presets = self.list()?;
+        let Some(preset) = presets.iter().find(|preset| preset.name == name) else {
+            let names: Vec<_> = presets.iter().map(|preset| preset.name.as_str()).collect();
+            return Err(PrinterError::InvalidInput(
+                format!("unknown preset '{}', available: {}", name, names.join(", "))
+            ));
+        };
+        match &preset.source {
+            PresetSource::Builtin => builtin(name),
+            PresetSource::File(path) => {
+                let file = File::open(path)
+                    .map_err(|e| PrinterError::io(format!("can't read preset {}", path.display()), e))?;
+                Tileset::read_as(Box::new(file), Some(TilesetFormat::Csv), None)
+                    .map_err(|e| e.in_file(path))
+            }
+        }
+    }
+}
+
+/// Built-in preset by name
+pub(super) fn builtin(name: &str) -> PrinterResult<Tileset> {
+    let Some((_, data)) = BUILTIN_PRESETS.iter().find(|(n, _)| *n == name) else {
+        return Err(PrinterError::InvalidInput(
+            format!("unknown built-in preset '{}', available: {}", name, Presets::builtin_names().join(", "))
+        ));
+    };
+    Tileset::read_as(Box::new(data.as_bytes()), Some(TilesetFormat::Csv), None)
+}
+
+/// Preset files of `dir` in name order
+fn preset_files(dir: &Path) -> PrinterResult<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(PrinterError::io(format!("can't read preset directory {}", dir.display()), e)),
+    };
+    let mut files = vec![];
+    for entry in entries {
+        let path = entry.map_err(|e| PrinterError::io(format!("can't read preset directory {}", dir.display()), e))?.path();
+        if path.is_file() && path.extension() == Some(OsStr::new(PRESET_EXTENSION)) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
@@ -0,0 +1,110 @@
+use image::{imageops, Rgba, RgbaImage};
+use crate::printer::{TileKind, Tileset, TilesetCheck};
+use crate::printer::font::{draw_text, GLYPH_ADVANCE, GLYPH_HEIGHT};
+
+const DEFAULT_SCALE: u32 = 2;
+/// Entries per column when the column count isn't set
+const DEFAULT_COLUMN_ROWS: usize = 32;
+const PADDING: u32 = 3;
+/// Swatch side and row height, one pixel of border plus one of space around a line of text
+const SWATCH_SIDE: u32 = GLYPH_HEIGHT + 4;
+const ROW_GAP: u32 = 2;
+const SWATCH_GAP: u32 = 4;
+const COLUMN_GAP: u32 = 12;
+
+const BACKGROUND: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const TEXT: Rgba<u8> = Rgba([0, 0, 0, 255]);
+const FLAG: Rgba<u8> = Rgba([208, 0, 0, 255]);
+
+/// Legend of a tileset: a swatch per entry in tileset order, labeled with
+/// `T` for tiles or `E` for entities, hex color and name. Entries that `TilesetCheck`
+/// finds a problem with, e.g. colors too close to another entry, are flagged with a red `!`
+pub struct TilesetPreview<'a> {
+    tileset: &'a Tileset,
+    scale: u32,
+    columns: Option<u32>,
+}
+
+impl<'a> TilesetPreview<'a> {
+    pub fn new(tileset: &'a Tileset) -> Self {
+        TilesetPreview { tileset, scale: DEFAULT_SCALE, columns: None }
+    }
+
+    /// Pixel size of one font pixel
+    pub fn scale(mut self, value: u32) -> Self {
+        self.scale = value.max(1);
+        self
+    }
+
+    /// Entries flow top to bottom, then into the next column
+    pub fn columns(mut self, value: u32) -> Self {
+        self.columns = Some(value.max(1));
+        self
+    }
+
+    pub fn render(&self) -> RgbaImage {
+        let count = self.tileset.len();
+        let columns = match self.columns {
+            Some(columns) => columns as usize,
+            None => ((count as f64 / DEFAULT_COLUMN_ROWS as f64).ceil() as usize).max(1),
+        };
+        let rows = ((count as f64 / columns as f64).ceil() as usize).max(1);
+        let columns = ((count as f64 / rows as f64).ceil() as usize).max(1);
+
+        let mut flagged = vec![false; count];
+        for problem in TilesetCheck::new().check(self.tileset) {
+            if let Some(entry) = problem.entry {
+                flagged[entry] = true;
+            }
+        }
+        let labels: Vec<String> = self.tileset.iter().enumerate().map(|(index, tile)| {
+            let marker = match tile.kind() {
+                TileKind::Tile => 'T',
+                TileKind::Entity => 'E',
+            };
+            let [r, g, b] = self.tileset.color(index).0;
+            format!("{} #{:02x}{:02x}{:02x} {}", marker, r, g, b, tile.name())
+        }).collect();
+
+        // flag and a space go before the label
+        let text_width = |chars: usize| (chars as u32 + 2) * GLYPH_ADVANCE;
+        let column_widths: Vec<u32> = (0..columns).map(|column| {
+            let longest = labels.iter().skip(column * rows).take(rows).map(|label| label.chars().count()).max();
+            SWATCH_SIDE + SWATCH_GAP + text_width(longest.unwrap_or(0))
+        }).collect();
+        let width = PADDING * 2 + column_widths.iter().sum::<u32>() + COLUMN_GAP * (columns as u32 - 1);
+        let height = PADDING * 2 + rows as u32 * SWATCH_SIDE + (rows as u32 - 1) * ROW_GAP;
+        let mut image = RgbaImage::from_pixel(width, height, BACKGROUND);
+
+        let mut left = PADDING;
+        for (column, column_width) in column_widths.iter().enumerate() {
+            for row in 0..rows {
+                let index = column * rows + row;
+                if index >= count {
+                    break;
+                }
+                let top = PADDING + row as u32 * (SWATCH_SIDE + ROW_GAP);
+                let color = self.tileset.color(index).0;
+                for y in 0..SWATCH_SIDE {
+                    for x in 0..SWATCH_SIDE {
+                        let border = x == 0 || y == 0 || x == SWATCH_SIDE - 1 || y == SWATCH_SIDE - 1;
+                        let pixel = if border { TEXT } else { Rgba([color[0], color[1], color[2], 255]) };
+                        image.put_pixel(left + x, top + y, pixel);
+                    }
+                }
+                let text_left = left + SWATCH_SIDE + SWATCH_GAP;
+                let text_top = top + (SWATCH_SIDE - GLYPH_HEIGHT) / 2;
+                if flagged[index] {
+                    draw_text(&mut image, text_left, text_top, "!", FLAG);
+                }
+                draw_text(&mut image, text_left + 2 * GLYPH_ADVANCE, text_top, &labels[index], TEXT);
+            }
+            left += column_width + COLUMN_GAP;
+        }
+
+        if self.scale == 1 {
+            return image;
+        }
+        imageops::resize(&image, width * self.scale, height * self.scale, imageops::FilterType::Nearest)
+    }
+}
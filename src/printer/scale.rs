@@ -0,0 +1,201 @@
+use image::{ImageBuffer, Rgba, Rgba32FImage, RgbaImage};
+use image::imageops::{resize, FilterType};
+use rayon::prelude::*;
+use crate::printer::color::{linear_to_srgb, srgb_to_linear_table};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+    /// Box averaging weighted by covered area, best for large downscales
+    Area,
+}
+
+const RESIZE_FILTERS: [(&str, ResizeFilter); 6] = [
+    ("nearest", ResizeFilter::Nearest),
+    ("triangle", ResizeFilter::Triangle),
+    ("catmull-rom", ResizeFilter::CatmullRom),
+    ("gaussian", ResizeFilter::Gaussian),
+    ("lanczos3", ResizeFilter::Lanczos3),
+    ("area", ResizeFilter::Area),
+];
+
+impl ResizeFilter {
+    pub fn names() -> Vec<&'static str> {
+        RESIZE_FILTERS.iter().map(|(name, _)| *name).collect()
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        RESIZE_FILTERS.iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, filter)| *filter)
+    }
+
+    fn filter_type(&self) -> Option<FilterType> {
+        match self {
+            ResizeFilter::Nearest => Some(FilterType::Nearest),
+            ResizeFilter::Triangle => Some(FilterType::Triangle),
+            ResizeFilter::CatmullRom => Some(FilterType::CatmullRom),
+            ResizeFilter::Gaussian => Some(FilterType::Gaussian),
+            ResizeFilter::Lanczos3 => Some(FilterType::Lanczos3),
+            ResizeFilter::Area => None,
+        }
+    }
+}
+
+/// Output size, everything but `Scale` is in pixels. Aspect ratio is always kept
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TargetSize {
+    Scale(f32),
+    Width(u32),
+    Height(u32),
+    /// Largest size that fits into the box
+    Fit(u32, u32),
+}
+
+pub struct Resizer {
+    size: TargetSize,
+    filter: ResizeFilter,
+    linear_light: bool,
+}
+
+impl Resizer {
+    pub fn new(size: TargetSize) -> Self {
+        Resizer {
+            size,
+            filter: ResizeFilter::CatmullRom,
+            linear_light: false,
+        }
+    }
+
+    pub fn filter(mut self, value: ResizeFilter) -> Self {
+        self.filter = value;
+        self
+    }
+
+    /// Resample linear light values instead of sRGB ones, keeps gradients from darkening
+    pub fn linear_light(mut self, value: bool) -> Self {
+        self.linear_light = value;
+        self
+    }
+
+    pub fn target_dimensions(&self, width: u32, height: u32) -> (u32, u32) {
+        let (width_f, height_f) = (width as f64, height as f64);
+        let (x, y) = match self.size {
+            TargetSize::Scale(scale) => (width_f * scale as f64, height_f * scale as f64),
+            TargetSize::Width(w) => (w as f64, height_f * w as f64 / width_f),
+            TargetSize::Height(h) => (width_f * h as f64 / height_f, h as f64),
+            TargetSize::Fit(w, h) => {
+                let scale = (w as f64 / width_f).min(h as f64 / height_f);
+                (width_f * scale, height_f * scale)
+            }
+        };
+        ((x.round() as u32).max(1), (y.round() as u32).max(1))
+    }
+
+    pub fn resize(&self, image: RgbaImage) -> RgbaImage {
+        let (width, height) = image.dimensions();
+        let (target_x, target_y) = self.target_dimensions(width, height);
+        if (target_x, target_y) == (width, height) {
+            return image;
+        }
+        if self.linear_light {
+            return self.resize_linear(&image, target_x, target_y);
+        }
+        match self.filter.filter_type() {
+            Some(filter) => resize(&image, target_x, target_y, filter),
+            None => {
+                let pixels = resize_area(&image, |pixel| pixel.0.map(f32::from), target_x, target_y);
+                ImageBuffer::from_fn(target_x, target_y, |x, y| {
+                    Rgba(pixels[(y * target_x + x) as usize].map(|v| v.round().clamp(0.0, 255.0) as u8))
+                })
+            }
+        }
+    }
+
+    /// Channels are decoded to linear 0..1 floats, alpha is scaled to 0..1 as is
+    fn resize_linear(&self, image: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+        let table = srgb_to_linear_table();
+        let decode = |pixel: &Rgba<u8>| {
+            let [r, g, b, a] = pixel.0;
+            [table[r as usize], table[g as usize], table[b as usize], a as f32 / 255.0]
+        };
+        let encode = |value: [f32; 4]| Rgba([
+            linear_to_srgb(value[0] as f64),
+            linear_to_srgb(value[1] as f64),
+            linear_to_srgb(value[2] as f64),
+            (value[3] * 255.0).round().clamp(0.0, 255.0) as u8,
+        ]);
+        match self.filter.filter_type() {
+            Some(filter) => {
+                let linear: Rgba32FImage = ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+                    Rgba(decode(image.get_pixel(x, y)))
+                });
+                let resized = resize(&linear, width, height, filter);
+                ImageBuffer::from_fn(width, height, |x, y| encode(resized.get_pixel(x, y).0))
+            }
+            None => {
+                let pixels = resize_area(image, decode, width, height);
+                ImageBuffer::from_fn(width, height, |x, y| encode(pixels[(y * width + x) as usize]))
+            }
+        }
+    }
+}
+
+/// For every output pixel along one axis: (first source pixel, weights of covered source pixels)
+fn area_weights(src: u32, dst: u32) -> Vec<(usize, Vec<f32>)> {
+    let ratio = src as f64 / dst as f64;
+    (0..dst).map(|o| {
+        let start = o as f64 * ratio;
+        let end = (o as f64 + 1.0) * ratio;
+        let first = start.floor() as usize;
+        let last = (end.ceil() as usize).min(src as usize);
+        let weights: Vec<f32> = (first..last).map(|i| {
+            let covered = end.min(i as f64 + 1.0) - start.max(i as f64);
+            (covered / ratio) as f32
+        }).collect();
+        (first, weights)
+    }).collect()
+}
+
+/// Area averaging of `decode`d pixels, result is row-major
+fn resize_area<F>(image: &RgbaImage, decode: F, width: u32, height: u32) -> Vec<[f32; 4]>
+where F: Fn(&Rgba<u8>) -> [f32; 4] + Sync {
+    let (src_width, src_height) = image.dimensions();
+    let columns = area_weights(src_width, width);
+    let rows = area_weights(src_height, height);
+
+    // horizontal pass into floats, then vertical pass into the output
+    let mut horizontal = vec![[0f32; 4]; width as usize * src_height as usize];
+    horizontal.par_chunks_mut(width as usize).enumerate().for_each(|(y, row)| {
+        for (x, (first, weights)) in columns.iter().enumerate() {
+            let mut sum = [0f32; 4];
+            for (i, weight) in weights.iter().enumerate() {
+                let pixel = decode(image.get_pixel((first + i) as u32, y as u32));
+                for (c, value) in sum.iter_mut().enumerate() {
+                    *value += pixel[c] * weight;
+                }
+            }
+            row[x] = sum;
+        }
+    });
+
+    let mut out = vec![[0f32; 4]; width as usize * height as usize];
+    out.par_chunks_mut(width as usize).enumerate().for_each(|(y, row)| {
+        let (first, weights) = &rows[y];
+        for x in 0..width as usize {
+            let mut sum = [0f32; 4];
+            for (i, weight) in weights.iter().enumerate() {
+                let pixel = horizontal[(first + i) * width as usize + x];
+                for (c, value) in sum.iter_mut().enumerate() {
+                    *value += pixel[c] * weight;
+                }
+            }
+            row[x] = sum;
+        }
+    });
+    out
+}
@@ -1,149 +1,262 @@
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Deserialize, Serializer};
+use serde_json::{Map, Value};
+use crate::printer::PrinterError;
+use crate::PrinterResult;
+
+/// Fields this crate doesn't model, kept as is for round-trips
+pub type FactorioExtra = Map<String, Value>;
 
 #[derive(Debug, Serialize, Deserialize)]
-struct FactorioBlueprintInternal {
-    item: String,
-    label: String,
-    entities: Vec<FactorioEntity>,
-    tiles: Vec<FactorioTile>,
-    icons: Vec<FactorioIcon>,
-    #[serde(skip_serializing)]
-    entity_counter: i32
+pub struct FactorioBlueprintInternal {
+    pub(crate) item: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) entities: Vec<FactorioEntity>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) tiles: Vec<FactorioTile>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) icons: Vec<FactorioIcon>,
+    #[serde(rename = "snap-to-grid", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) snap_to_grid: Option<FactorioPosition>,
+    #[serde(rename = "absolute-snapping", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) absolute_snapping: Option<bool>,
+    #[serde(rename = "position-relative-to-grid", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) position_relative_to_grid: Option<FactorioPosition>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) version: Option<u64>,
+    #[serde(flatten)]
+    pub(crate) extra: FactorioExtra,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FactorioBlueprint {
-    blueprint: FactorioBlueprintInternal
+    pub(crate) blueprint: FactorioBlueprintInternal
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FactorioSignal {
     pub(crate) name: String,
-    #[serde(rename="type")]
-    pub(crate) signal_type: String
+    #[serde(rename="type", default, skip_serializing_if = "Option::is_none")]
+    pub(crate) signal_type: Option<String>,
+    #[serde(flatten)]
+    pub(crate) extra: FactorioExtra,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FactorioIcon {
     pub(crate) index: i32,  // 1-based
-    pub(crate) signal: FactorioSignal
+    pub(crate) signal: FactorioSignal,
+    #[serde(flatten)]
+    pub(crate) extra: FactorioExtra,
+}
+
+/// Upgrade and deconstruction planners, their settings are kept as passthrough
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FactorioPlannerInternal {
+    pub(crate) item: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) version: Option<u64>,
+    #[serde(flatten)]
+    pub(crate) extra: FactorioExtra,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FactorioBookBlueprintVecElement {
+    pub(crate) index: i32, // 0-based
+    #[serde(flatten)]
+    pub(crate) content: FactorioBlueprintString,
 }
 
+/// `B` is the type of the slot list, anything serializing as a sequence of
+/// `FactorioBookBlueprintVecElement` when writing, see `FactorioBook::with_blueprints`
 #[derive(Debug, Serialize, Deserialize)]
-struct FactorioBookBlueprintVecElement {
-    index: i32, // 0-based
-    blueprint: FactorioBlueprintInternal
+#[serde(bound(deserialize = "B: Deserialize<'de> + Default"))]
+pub struct FactorioBookInternal<B = Vec<FactorioBookBlueprintVecElement>> {
+    pub(crate) item: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) icons: Vec<FactorioIcon>,
+    #[serde(default)]
+    pub(crate) blueprints: B,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) active_index: Option<i32>,  // 0-based
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) version: Option<u64>,
+    #[serde(flatten)]
+    pub(crate) extra: FactorioExtra,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-struct FactorioBookInternal {
-    item: String,
-    label: String,
-    blueprints: Vec<FactorioBookBlueprintVecElement>,
-    active_index: i32,  // 0-based
-    version: i64
+#[serde(bound(deserialize = "B: Deserialize<'de> + Default"))]
+pub struct FactorioBook<B = Vec<FactorioBookBlueprintVecElement>> {
+    pub(crate) blueprint_book: FactorioBookInternal<B>
 }
 
+/// Anything that can be found inside a blueprint string or a book slot
 #[derive(Debug, Serialize, Deserialize)]
-pub struct FactorioBook {
-    blueprint_book: FactorioBookInternal
+#[serde(rename_all = "snake_case")]
+pub enum FactorioBlueprintString {
+    Blueprint(FactorioBlueprintInternal),
+    BlueprintBook(FactorioBookInternal),
+    UpgradePlanner(FactorioPlannerInternal),
+    DeconstructionPlanner(FactorioPlannerInternal),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FactorioEntity {
-    entity_number: i32,  // 1-based
-    name: String,
-    position: FactorioPosition
+    pub(crate) entity_number: i32,  // 1-based
+    pub(crate) name: String,
+    pub(crate) position: FactorioPosition,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) direction: Option<u8>,
+    #[serde(flatten)]
+    pub(crate) extra: FactorioExtra,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FactorioTile {
-    name: String,
-    position: FactorioPosition
+    pub(crate) name: String,
+    pub(crate) position: FactorioPosition,
+    #[serde(flatten)]
+    pub(crate) extra: FactorioExtra,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FactorioPosition {
-    x: i32,
-    y: i32
+    #[serde(serialize_with = "serialize_coord")]
+    pub(crate) x: f64,
+    #[serde(serialize_with = "serialize_coord")]
+    pub(crate) y: f64,
+    #[serde(flatten)]
+    pub(crate) extra: FactorioExtra,
+}
+
+/// Whole coordinates are written without fraction, the same way the game does
+fn serialize_coord<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    if value.fract() == 0.0 && value.abs() < i64::MAX as f64 {
+        serializer.serialize_i64(*value as i64)
+    } else {
+        serializer.serialize_f64(*value)
+    }
+}
+
+impl FactorioPosition {
+    fn new(x: i32, y: i32) -> Self {
+        FactorioPosition { x: x as f64, y: y as f64, extra: FactorioExtra::new() }
+    }
+
+    /// Grid cell this position falls into
+    pub(crate) fn cell(&self) -> (i32, i32) {
+        (self.x.floor() as i32, self.y.floor() as i32)
+    }
 }
 
 impl FactorioBlueprint {
     pub fn new() -> FactorioBlueprint {
         let bp = FactorioBlueprintInternal {
             item: "blueprint".to_string(),
-            label: "Blueprint".to_string(),
+            label: Some("Blueprint".to_string()),
+            description: None,
             entities: vec![],
             tiles: vec![],
             icons: vec![],
-            entity_counter: 1
+            snap_to_grid: None,
+            absolute_snapping: None,
+            position_relative_to_grid: None,
+            version: None,
+            extra: FactorioExtra::new(),
         };
         FactorioBlueprint{ blueprint: bp }
     }
 
     pub fn add_entity(&mut self, name: &str, x: i32, y: i32) {
         self.blueprint.entities.push(FactorioEntity{
-            entity_number: self.blueprint.entity_counter,
+            entity_number: self.blueprint.entities.len() as i32 + 1,
             name: name.to_string(),
-            position: FactorioPosition {x, y}
+            position: FactorioPosition::new(x, y),
+            direction: None,
+            extra: FactorioExtra::new(),
         });
-        self.blueprint.entity_counter += 1;
     }
 
     pub fn add_tile(&mut self, name: &str, x: i32, y: i32) {
         self.blueprint.tiles.push(FactorioTile{
             name: name.to_string(),
-            position: FactorioPosition {x, y}
+            position: FactorioPosition::new(x, y),
+            extra: FactorioExtra::new(),
         });
     }
 
     fn digit_as_signal(&self, value: i32) -> FactorioSignal {
-        FactorioSignal { name: format!("signal-{}", value), signal_type: "virtual".to_string() }
+        FactorioSignal {
+            name: format!("signal-{}", value),
+            signal_type: Some("virtual".to_string()),
+            extra: FactorioExtra::new(),
+        }
     }
 
-    pub fn set_icons(&mut self, value: i32) {
-        if value > 9999 || value < 0 {
-            panic!("set_icons: value should be between 0 and 9999")
+    /// Shows `value` as four digit signals, it must be in 0..=9999
+    pub fn set_icons(&mut self, value: i32) -> PrinterResult<()> {
+        if !(0..=9999).contains(&value) {
+            return Err(PrinterError::BlueprintEncode {
+                message: format!("blueprint icons can't show {}, value should be between 0 and 9999", value),
+                source: None,
+            });
         }
         let digit0 = (value / 1000) % 10;
         let digit1 = (value / 100) % 10;
         let digit2 = (value / 10) % 10;
         let digit3 = value % 10;
         self.blueprint.icons = vec![
-            FactorioIcon { index: 1, signal: self.digit_as_signal(digit0)},
-            FactorioIcon { index: 2, signal: self.digit_as_signal(digit1)},
-            FactorioIcon { index: 3, signal: self.digit_as_signal(digit2)},
-            FactorioIcon { index: 4, signal: self.digit_as_signal(digit3)},
+            FactorioIcon { index: 1, signal: self.digit_as_signal(digit0), extra: FactorioExtra::new() },
+            FactorioIcon { index: 2, signal: self.digit_as_signal(digit1), extra: FactorioExtra::new() },
+            FactorioIcon { index: 3, signal: self.digit_as_signal(digit2), extra: FactorioExtra::new() },
+            FactorioIcon { index: 4, signal: self.digit_as_signal(digit3), extra: FactorioExtra::new() },
         ];
+        Ok(())
     }
 
     pub fn set_label(&mut self, label: String) {
-        self.blueprint.label = label;
+        self.blueprint.label = Some(label);
+    }
+
+    /// Grid the blueprint snaps to when placed, `side` tiles square
+    pub fn set_snap_to_grid(&mut self, side: i32) {
+        self.blueprint.snap_to_grid = Some(FactorioPosition::new(side, side));
     }
 }
 
-impl FactorioBook {
-    pub fn new() -> FactorioBook {
+impl<B> FactorioBook<B> {
+    /// Book with `blueprints` as its slot list
+    pub fn with_blueprints(label: String, blueprints: B) -> FactorioBook<B> {
         let book = FactorioBookInternal {
             item: "blueprint-book".to_string(),
-            label: "Book".to_string(),
-            blueprints: vec![],
-            active_index: 0,
-            version: 0
+            label: Some(label),
+            description: None,
+            icons: vec![],
+            blueprints,
+            active_index: Some(0),
+            version: Some(0),
+            extra: FactorioExtra::new(),
         };
         FactorioBook{ blueprint_book: book }
     }
+}
 
-    pub fn add_blueprint(&mut self, bp: FactorioBlueprint) {
-        self.blueprint_book.blueprints.push(
-            FactorioBookBlueprintVecElement {
-                index: self.blueprint_book.blueprints.len() as i32,
-                blueprint: bp.blueprint
-            }
-        );
-    }
-
-    pub fn set_label(&mut self, label: String) {
-        self.blueprint_book.label = label;
+impl FactorioBookBlueprintVecElement {
+    /// Slot `index` of a book holding `bp`
+    pub fn blueprint(index: i32, bp: FactorioBlueprint) -> Self {
+        FactorioBookBlueprintVecElement {
+            index,
+            content: FactorioBlueprintString::Blueprint(bp.blueprint),
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,418 @@
+use std::fmt;
+use std::str::FromStr;
+use image::Rgb;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{Error as _, SeqAccess, Visitor};
+
+/// Which color profile of tileset entries is used for matching and rendering
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorView {
+    /// Colors as seen on the map, `color` column
+    #[default]
+    Map,
+    /// Colors as seen in normal game view, `ground` column
+    Ground,
+    /// User defined colors, `custom` column
+    Custom,
+}
+
+const COLOR_VIEWS: [(&str, ColorView); 3] = [
+    ("map", ColorView::Map),
+    ("ground", ColorView::Ground),
+    ("custom", ColorView::Custom),
+];
+
+impl ColorView {
+    pub fn names() -> Vec<&'static str> {
+        COLOR_VIEWS.iter().map(|(name, _)| *name).collect()
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        COLOR_VIEWS.iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, view)| *view)
+    }
+}
+
+/// Color written as `#rrggbb` in tileset files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct HexColor(pub(crate) [u8; 3]);
+
+impl fmt::Display for HexColor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [r, g, b] = self.0;
+        write!(f, "#{:02x}{:02x}{:02x}", r, g, b)
+    }
+}
+
+impl FromStr for HexColor {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let hex = value.trim().trim_start_matches('#');
+        let channel = |i: usize| hex.get(i * 2..i * 2 + 2).and_then(|c| u8::from_str_radix(c, 16).ok());
+        // from_str_radix alone would take a sign, as in "#+1+2+3"
+        let digits = hex.bytes().all(|b| b.is_ascii_hexdigit());
+        match (hex.len(), digits, channel(0), channel(1), channel(2)) {
+            (6, true, Some(r), Some(g), Some(b)) => Ok(HexColor([r, g, b])),
+            _ => Err(format!("expected color as #rrggbb, got '{}'", value)),
+        }
+    }
+}
+
+/// What an entry is placed as in a blueprint
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TileKind {
+    Tile,
+    Entity,
+}
+
+/// Size in tiles, written as `WxH`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Footprint {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl fmt::Display for Footprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+impl FromStr for Footprint {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let size = value.split_once(['x', 'X'])
+            .and_then(|(w, h)| Some((w.trim().parse().ok()?, h.trim().parse().ok()?)));
+        match size {
+            Some((width, height)) if width > 0 && height > 0 => Ok(Footprint { width, height }),
+            _ => Err(format!("expected footprint as WxH, got '{}'", value)),
+        }
+    }
+}
+
+/// Direction an entity may be placed in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    North,
+    East,
+    South,
+    West,
+}
+
+const DIRECTIONS: [(&str, Direction); 4] = [
+    ("north", Direction::North),
+    ("east", Direction::East),
+    ("south", Direction::South),
+    ("west", Direction::West),
+];
+
+impl Direction {
+    pub fn names() -> Vec<&'static str> {
+        DIRECTIONS.iter().map(|(name, _)| *name).collect()
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        DIRECTIONS.iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, direction)| *direction)
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = DIRECTIONS.iter()
+            .find(|(_, direction)| direction == self)
+            .map(|(name, _)| *name)
+            .expect("every direction has a name");
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Direction {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::from_name(value.trim())
+            .ok_or_else(|| format!("expected one of {}, got '{}'", Self::names().join(", "), value))
+    }
+}
+
+/// Serialized as strings through `Display` and `FromStr`
+macro_rules! string_serde {
+    ($($type:ty),*) => {$(
+        impl Serialize for $type {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $type {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                String::deserialize(deserializer)?.parse().map_err(D::Error::custom)
+            }
+        }
+    )*};
+}
+
+string_serde!(HexColor, Footprint, Direction);
+
+/// Items of a `;` separated list, empty ones left out
+fn split_list(value: &str) -> Vec<String> {
+    value.split(';').map(str::trim).filter(|item| !item.is_empty()).map(str::to_string).collect()
+}
+
+fn parse_list<T: FromStr>(value: &str) -> Result<Vec<T>, T::Err> {
+    split_list(value).iter().map(|item| item.parse()).collect()
+}
+
+/// Lists are arrays in JSON and TOML, or `;` separated strings. CSV rows go through `TileCsv`
+fn deserialize_list<'de, T, D>(deserializer: D) -> Result<Vec<T>, D::Error>
+where D: Deserializer<'de>, T: FromStr, T::Err: fmt::Display {
+    struct ListVisitor;
+
+    impl<'de> Visitor<'de> for ListVisitor {
+        type Value = Vec<String>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("list or ';' separated string")
+        }
+
+        fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Self::Value, E> {
+            Ok(split_list(value))
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut items = vec![];
+            while let Some(item) = seq.next_element::<String>()? {
+                items.push(item);
+            }
+            Ok(items)
+        }
+    }
+
+    deserializer.deserialize_any(ListVisitor)?.iter()
+        .map(|item| item.parse().map_err(D::Error::custom))
+        .collect()
+}
+
+/// Tileset entry. `color` is the map view color,
+/// other views fall back to it when they aren't set
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Tile {
+    pub(crate) name: String,
+    pub(crate) kind: TileKind,
+    pub(crate) color: HexColor,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) ground: Option<HexColor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) custom: Option<HexColor>,
+    /// Other names of the same thing, e.g. from older game versions
+    #[serde(default, skip_serializing_if = "Vec::is_empty", deserialize_with = "deserialize_list")]
+    pub(crate) aliases: Vec<String>,
+    /// Free-form tags, e.g. "concrete" or "mod:color-coding"
+    #[serde(default, skip_serializing_if = "Vec::is_empty", deserialize_with = "deserialize_list")]
+    pub(crate) groups: Vec<String>,
+    /// Relative price of one piece, 1 when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) cost: Option<f32>,
+    /// 1x1 when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) footprint: Option<Footprint>,
+    /// Any direction when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty", deserialize_with = "deserialize_list")]
+    pub(crate) directions: Vec<Direction>,
+    /// Tile that has to be under the entity
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) under_tile: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) notes: Option<String>,
+    /// Line in the source file, for diagnostics
+    #[serde(skip)]
+    pub(crate) line: Option<usize>,
+}
+
+impl Tile {
+    pub fn new(name: &str, kind: TileKind, color: Rgb<u8>) -> Self {
+        Tile {
+            name: name.to_string(),
+            kind,
+            color: HexColor(color.0),
+            ground: None,
+            custom: None,
+            aliases: vec![],
+            groups: vec![],
+            cost: None,
+            footprint: None,
+            directions: vec![],
+            under_tile: None,
+            notes: None,
+            line: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn kind(&self) -> TileKind {
+        self.kind
+    }
+
+    /// Color in `view`, map color if the entry has no such profile
+    pub fn color(&self, view: ColorView) -> Rgb<u8> {
+        self.profile(view).unwrap_or(Rgb::from(self.color.0))
+    }
+
+    /// Color of `view` profile without falling back to map color
+    pub fn profile(&self, view: ColorView) -> Option<Rgb<u8>> {
+        let color = match view {
+            ColorView::Map => Some(self.color),
+            ColorView::Ground => self.ground,
+            ColorView::Custom => self.custom,
+        };
+        color.map(|color| Rgb::from(color.0))
+    }
+
+    pub fn set_color(&mut self, view: ColorView, color: Rgb<u8>) {
+        let color = HexColor(color.0);
+        match view {
+            ColorView::Map => self.color = color,
+            ColorView::Ground => self.ground = Some(color),
+            ColorView::Custom => self.custom = Some(color),
+        }
+    }
+
+    /// `name` is the entry name or one of its aliases
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|alias| alias == name)
+    }
+
+    pub fn aliases(&self) -> &[String] {
+        &self.aliases
+    }
+
+    pub fn set_aliases(&mut self, aliases: Vec<String>) {
+        self.aliases = aliases;
+    }
+
+    pub fn groups(&self) -> &[String] {
+        &self.groups
+    }
+
+    pub fn set_groups(&mut self, groups: Vec<String>) {
+        self.groups = groups;
+    }
+
+    pub fn cost(&self) -> Option<f32> {
+        self.cost
+    }
+
+    pub fn set_cost(&mut self, cost: Option<f32>) {
+        self.cost = cost;
+    }
+
+    pub fn footprint(&self) -> Option<Footprint> {
+        self.footprint
+    }
+
+    pub fn set_footprint(&mut self, footprint: Option<Footprint>) {
+        self.footprint = footprint;
+    }
+
+    pub fn directions(&self) -> &[Direction] {
+        &self.directions
+    }
+
+    pub fn set_directions(&mut self, directions: Vec<Direction>) {
+        self.directions = directions;
+    }
+
+    pub fn under_tile(&self) -> Option<&str> {
+        self.under_tile.as_deref()
+    }
+
+    pub fn set_under_tile(&mut self, name: Option<String>) {
+        self.under_tile = name;
+    }
+
+    pub fn notes(&self) -> Option<&str> {
+        self.notes.as_deref()
+    }
+
+    pub fn set_notes(&mut self, notes: Option<String>) {
+        self.notes = notes;
+    }
+}
+
+/// CSV row of the current format. CSV guesses cell types, so lists are read
+/// as plain strings here, otherwise a numeric looking alias like `1` fails to parse
+#[derive(Deserialize, Debug)]
+pub(crate) struct TileCsv {
+    name: String,
+    kind: TileKind,
+    color: HexColor,
+    #[serde(default)]
+    ground: Option<HexColor>,
+    #[serde(default)]
+    custom: Option<HexColor>,
+    #[serde(default)]
+    aliases: String,
+    #[serde(default)]
+    groups: String,
+    #[serde(default)]
+    cost: Option<f32>,
+    #[serde(default)]
+    footprint: Option<Footprint>,
+    #[serde(default)]
+    directions: String,
+    #[serde(default)]
+    under_tile: Option<String>,
+    #[serde(default)]
+    notes: Option<String>,
+}
+
+impl TryFrom<TileCsv> for Tile {
+    type Error = String;
+
+    fn try_from(row: TileCsv) -> Result<Self, Self::Error> {
+        let mut tile = Tile::new(&row.name, row.kind, Rgb::from(row.color.0));
+        tile.ground = row.ground;
+        tile.custom = row.custom;
+        tile.aliases = split_list(&row.aliases);
+        tile.groups = split_list(&row.groups);
+        tile.cost = row.cost;
+        tile.footprint = row.footprint;
+        tile.directions = parse_list(&row.directions)?;
+        tile.under_tile = row.under_tile;
+        tile.notes = row.notes;
+        Ok(tile)
+    }
+}
+
+/// First tileset format: `red,green,blue,name,is_tile` with optional color profiles
+#[derive(Deserialize, Debug)]
+pub(crate) struct TileV1 {
+    red: u8,
+    green: u8,
+    blue: u8,
+    name: String,
+    is_tile: bool,
+    #[serde(default)]
+    ground: Option<HexColor>,
+    #[serde(default)]
+    custom: Option<HexColor>,
+}
+
+impl From<TileV1> for Tile {
+    fn from(v1: TileV1) -> Self {
+        let kind = if v1.is_tile { TileKind::Tile } else { TileKind::Entity };
+        let mut tile = Tile::new(&v1.name, kind, Rgb::from([v1.red, v1.green, v1.blue]));
+        tile.ground = v1.ground;
+        tile.custom = v1.custom;
+        tile
+    }
+}
@@ -0,0 +1,423 @@
+//! `serve` command: a small blocking HTTP/1.1 server that prints images and renders blueprints.
+//! Every connection gets a thread of its own, up to `MAX_CONNECTIONS`. Every response closes the connection
+
+use std::error::Error;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use clap::ArgMatches;
+use factorio_printer::{printer, PrinterError, PrinterResult};
+use factorio_printer::printer::{BlueprintRenderer, TargetSize, Tileset};
+use crate::{get_blueprint_builder, get_image_printer, get_print_tileset, parse_size};
+
+/// Largest request body, images and blueprint strings included
+const MAX_BODY: usize = 64 << 20;
+/// Longest request or header line
+const MAX_LINE: u64 = 8 << 10;
+const MAX_HEADERS: usize = 64;
+/// Connections served at once, more get 503 right away
+const MAX_CONNECTIONS: usize = 32;
+/// Largest uploaded image, print and render, 4096x4096 or the same area
+const MAX_PIXELS: u64 = 1 << 24;
+/// Largest JSON a `/render` blueprint string may inflate to, parsed blueprints take a few times more
+const MAX_BLUEPRINT_JSON: u64 = 32 << 20;
+/// The whole request has to arrive within it, a write of the response waits at most as long
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Largest `split` query value, same as `--split`
+const MAX_SPLIT: i32 = 9999;
+
+const USAGE: &str = "factorio-printer\n\
+POST /print    image body, blueprint string back. Query: width, height, fit=WxH, scale, split\n\
+POST /render   blueprint string body, PNG back\n";
+
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.query.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+struct Response {
+    status: u16,
+    reason: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn new(content_type: &'static str, body: Vec<u8>) -> Self {
+        Response { status: 200, reason: "OK", content_type, body }
+    }
+
+    fn text(status: u16, reason: &'static str, text: &str) -> Self {
+        Response { status, reason, content_type: "text/plain; charset=utf-8", body: text.as_bytes().to_vec() }
+    }
+
+    /// Error with its causes. Bad input is the client's fault, everything else is ours
+    fn error(e: &PrinterError) -> Self {
+        let mut text = e.to_string();
+        let mut source = e.source();
+        while let Some(cause) = source {
+            text.push_str("\ncaused by: ");
+            text.push_str(&cause.to_string());
+            source = cause.source();
+        }
+        text.push('\n');
+        match e {
+            PrinterError::Io { .. } | PrinterError::BlueprintEncode { .. } =>
+                Response::text(500, "Internal Server Error", &text),
+            _ => Response::text(400, "Bad Request", &text),
+        }
+    }
+
+    fn write_to(&self, stream: &mut TcpStream) -> std::io::Result<()> {
+        write!(stream, "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status, self.reason, self.content_type, self.body.len())?;
+        stream.write_all(&self.body)?;
+        stream.flush()
+    }
+}
+
+/// Listens on `--listen` until killed. Options are checked once on start
+pub fn serve(args: &ArgMatches) -> PrinterResult<()> {
+    let tileset = Arc::new(get_print_tileset(args)?);
+    get_image_printer(&tileset, args)?;
+    let args = Arc::new(args.clone());
+    let active = Arc::new(AtomicUsize::new(0));
+    let address = args.get_one::<String>("listen").expect("listen default value");
+    let listener = TcpListener::bind(address)
+        .map_err(|e| PrinterError::io(format!("can't listen on {}", address), e))?;
+    eprintln!("listening on http://{}", address);
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("warning: can't accept connection: {}", e);
+                continue;
+            }
+        };
+        if active.fetch_add(1, Ordering::AcqRel) >= MAX_CONNECTIONS {
+            active.fetch_sub(1, Ordering::AcqRel);
+            let busy = Response::text(503, "Service Unavailable", "too many connections, try again later\n");
+            if let Err(e) = stream.set_write_timeout(Some(TIMEOUT)).and_then(|_| busy.write_to(&mut stream)) {
+                eprintln!("warning: can't send response: {}", e);
+            }
+            continue;
+        }
+        let (tileset, args, done) = (Arc::clone(&tileset), Arc::clone(&args), Arc::clone(&active));
+        let spawned = thread::Builder::new().spawn(move || {
+            respond(stream, &tileset, &args);
+            done.fetch_sub(1, Ordering::AcqRel);
+        });
+        if let Err(e) = spawned {
+            active.fetch_sub(1, Ordering::AcqRel);
+            eprintln!("warning: can't start connection thread: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Reads one request and answers it. A panic while handling it becomes a 500 response
+fn respond(mut stream: TcpStream, tileset: &Tileset, args: &ArgMatches) {
+    let response = match read_request(&stream) {
+        Ok(request) => panic::catch_unwind(AssertUnwindSafe(|| handle(&request, tileset, args)))
+            .unwrap_or_else(|_| Response::text(500, "Internal Server Error", "request failed unexpectedly\n")),
+        Err(response) => response,
+    };
+    let sent = stream.set_write_timeout(Some(TIMEOUT)).and_then(|_| response.write_to(&mut stream));
+    if let Err(e) = sent {
+        eprintln!("warning: can't send response: {}", e);
+    }
+}
+
+fn handle(request: &Request, tileset: &Tileset, args: &ArgMatches) -> Response {
+    let result = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/") => Ok(Response::text(200, "OK", USAGE)),
+        ("POST", "/print") => print(request, tileset, args).map(|text| Response::new("text/plain", text)),
+        ("POST", "/render") => render(request, tileset).map(|png| Response::new("image/png", png)),
+        (_, "/") | (_, "/print") | (_, "/render") => Ok(Response::text(405, "Method Not Allowed", USAGE)),
+        _ => Ok(Response::text(404, "Not Found", USAGE)),
+    };
+    let response = result.unwrap_or_else(|e| Response::error(&e));
+    eprintln!("{} {} {}", request.method, request.path, response.status);
+    response
+}
+
+/// Size from the query, the one from the command line when there's none
+fn target_size(request: &Request) -> PrinterResult<Option<TargetSize>> {
+    let number = |name: &str, value: &str| value.parse::<u32>().ok()
+        .filter(|value| *value > 0)
+        .ok_or_else(|| PrinterError::InvalidInput(format!("{} must be a positive number, got '{}'", name, value)));
+    if let Some(fit) = request.param("fit") {
+        let (w, h) = parse_size(fit)?;
+        return Ok(Some(TargetSize::Fit(w, h)));
+    }
+    if let Some(width) = request.param("width") {
+        return Ok(Some(TargetSize::Width(number("width", width)?)));
+    }
+    if let Some(height) = request.param("height") {
+        return Ok(Some(TargetSize::Height(number("height", height)?)));
+    }
+    if let Some(scale) = request.param("scale") {
+        let scale = scale.parse::<f32>().ok()
+            .filter(|scale| *scale > 0.0)
+            .ok_or_else(|| PrinterError::InvalidInput(format!("scale must be a positive number, got '{}'", scale)))?;
+        return Ok(Some(TargetSize::Scale(scale)));
+    }
+    Ok(None)
+}
+
+fn print(request: &Request, tileset: &Tileset, args: &ArgMatches) -> PrinterResult<Vec<u8>> {
+    let mut print = get_image_printer(tileset, args)?.max_pixels(MAX_PIXELS);
+    if let Some(size) = target_size(request)? {
+        print = print.size(size);
+    }
+    let (image, _) = printer::load_image_within(&request.body, MAX_PIXELS)?;
+    let (_, indexed) = print.print(image)?;
+    let mut builder = get_blueprint_builder("Printed image", &indexed, tileset, args);
+    if let Some(split) = request.param("split") {
+        let split = split.parse::<i32>().ok()
+            .filter(|split| (0..=MAX_SPLIT).contains(split))
+            .ok_or_else(|| PrinterError::InvalidInput(
+                format!("split must be between 0 and {}, got '{}'", MAX_SPLIT, split)
+            ))?;
+        builder = builder.split(split);
+    }
+    let mut out = vec![];
+    builder.factorio_serialize_to(&mut out)?;
+    Ok(out)
+}
+
+fn render(request: &Request, tileset: &Tileset) -> PrinterResult<Vec<u8>> {
+    let data = std::str::from_utf8(&request.body).map_err(|e| PrinterError::BlueprintDecode {
+        message: "blueprint string is not valid UTF-8".to_string(),
+        source: Some(e.into()),
+    })?;
+    let blueprint = printer::factorio_deserialize_within(data, MAX_BLUEPRINT_JSON)?;
+    // names missing from the tileset are the client's business, they just don't show up
+    let (image, _) = BlueprintRenderer::new(&blueprint, tileset).max_pixels(MAX_PIXELS).render()?;
+    let mut out = std::io::Cursor::new(vec![]);
+    image.write_to(&mut out, image::ImageFormat::Png)
+        .map_err(|e| PrinterError::image("can't encode PNG", e))?;
+    Ok(out.into_inner())
+}
+
+fn bad_request(text: &str) -> Response {
+    Response::text(400, "Bad Request", text)
+}
+
+/// Timeouts get 408, anything else is a malformed request
+fn read_error(e: std::io::Error, text: &str) -> Response {
+    match e.kind() {
+        ErrorKind::TimedOut | ErrorKind::WouldBlock => Response::text(408, "Request Timeout", "request took too long\n"),
+        _ => bad_request(text),
+    }
+}
+
+/// Reads from the stream until the deadline, however slowly the client sends
+struct DeadlineReader<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(left))?;
+        let mut stream = self.stream;
+        stream.read(buf)
+    }
+}
+
+/// Query component with `%XX` escapes and `+` for space decoded
+fn percent_decode(text: &str) -> Result<String, Response> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' => {
+                let byte = bytes.get(i + 1..i + 3)
+                    .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                    .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok())
+                    .ok_or_else(|| bad_request("malformed percent escape in query\n"))?;
+                out.push(byte);
+                i += 2;
+            }
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8(out).map_err(|_| bad_request("query is not valid UTF-8\n"))
+}
+
+/// One CRLF terminated line without the line break
+fn read_line<R: BufRead>(reader: &mut R) -> Result<String, Response> {
+    let mut line = String::new();
+    reader.take(MAX_LINE).read_line(&mut line).map_err(|e| read_error(e, "can't read request\n"))?;
+    if !line.ends_with('\n') {
+        return Err(bad_request("request line too long or cut short\n"));
+    }
+    Ok(line.trim_end().to_string())
+}
+
+/// Reads request line, headers and a `Content-Length` body within `TIMEOUT`.
+/// Errors come back as ready responses
+fn read_request(stream: &TcpStream) -> Result<Request, Response> {
+    let mut reader = BufReader::new(DeadlineReader { stream, deadline: Instant::now() + TIMEOUT });
+
+    let request_line = read_line(&mut reader)?;
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target), Some(_version)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(bad_request("malformed request line\n"));
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = query.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            Ok((percent_decode(key)?, percent_decode(value)?))
+        })
+        .collect::<Result<_, Response>>()?;
+
+    let mut length = 0;
+    for count in 0.. {
+        let header = read_line(&mut reader)?;
+        if header.is_empty() {
+            break;
+        }
+        if count == MAX_HEADERS {
+            return Err(bad_request("too many headers\n"));
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(bad_request("malformed header\n"));
+        };
+        if name.eq_ignore_ascii_case("content-length") {
+            length = value.trim().parse().map_err(|_| bad_request("bad Content-Length\n"))?;
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            return Err(Response::text(411, "Length Required", "send the body with Content-Length\n"));
+        }
+    }
+    if length > MAX_BODY {
+        return Err(Response::text(413, "Payload Too Large", "request body is too large\n"));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(|e| read_error(e, "request body cut short\n"))?;
+    Ok(Request { method: method.to_string(), path: path.to_string(), query, body })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::thread;
+    use image::{ImageFormat, RgbaImage};
+    use super::*;
+
+    /// Sends `request` over a real connection and handles what the server reads
+    fn exchange(request: Vec<u8>, args: &[&str]) -> Response {
+        let args = crate::serve_command().try_get_matches_from(args).unwrap();
+        let tileset = get_print_tileset(&args).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(&request).unwrap();
+            stream
+        });
+        let (stream, _) = listener.accept().unwrap();
+        let response = match read_request(&stream) {
+            Ok(request) => handle(&request, &tileset, &args),
+            Err(response) => response,
+        };
+        drop(client.join().unwrap());
+        response
+    }
+
+    fn post(target: &str, body: &[u8]) -> Vec<u8> {
+        let mut request = format!("POST {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n", target, body.len())
+            .into_bytes();
+        request.extend_from_slice(body);
+        request
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = RgbaImage::from_fn(width, height, |x, y| image::Rgba([(x * 40) as u8, (y * 40) as u8, 128, 255]));
+        let mut out = Cursor::new(vec![]);
+        image.write_to(&mut out, ImageFormat::Png).unwrap();
+        out.into_inner()
+    }
+
+    fn body(response: &Response) -> &str {
+        std::str::from_utf8(&response.body).unwrap()
+    }
+
+    #[test]
+    fn prints_with_percent_encoded_query() {
+        let response = exchange(post("/print?fit=4%784&split=0", &png(6, 6)), &["serve"]);
+        assert_eq!(response.status, 200, "{}", body(&response));
+        let blueprint = printer::factorio_deserialize(body(&response)).unwrap();
+        let (image, _) = BlueprintRenderer::new(&blueprint, &Tileset::preset_color_coding().unwrap()).render().unwrap();
+        assert_eq!(image.dimensions(), (4, 4));
+    }
+
+    #[test]
+    fn rejects_oversized_print() {
+        let response = exchange(post("/print?width=3000000000", &png(2, 2)), &["serve"]);
+        assert_eq!(response.status, 400);
+        assert!(body(&response).contains("at most"), "{}", body(&response));
+    }
+
+    #[test]
+    fn rejects_oversized_render() {
+        let blueprint = serde_json::json!({"blueprint": {
+            "item": "blueprint",
+            "tiles": [
+                {"name": "concrete", "position": {"x": 0, "y": 0}},
+                {"name": "concrete", "position": {"x": 100000, "y": 100000}}
+            ],
+            "version": 0
+        }});
+        let data = printer::factorio_encode(&blueprint).unwrap();
+        let response = exchange(post("/render", data.as_bytes()), &["serve", "-p", "base"]);
+        assert_eq!(response.status, 400);
+        assert!(body(&response).contains("100001x100001"), "{}", body(&response));
+    }
+
+    #[test]
+    fn rejects_blueprint_inflating_past_budget() {
+        // JSON that never ends, spaces compress about a thousand to one
+        let mut json = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::fast());
+        json.write_all(br#"{"blueprint":"#).unwrap();
+        let spaces = [b' '; 1 << 16];
+        for _ in 0..=MAX_BLUEPRINT_JSON as usize / spaces.len() {
+            json.write_all(&spaces).unwrap();
+        }
+        let data = format!("0{}", base64::Engine::encode(&base64::engine::general_purpose::STANDARD, json.finish().unwrap()));
+        assert!(data.len() < 1 << 20);
+        let response = exchange(post("/render", data.as_bytes()), &["serve", "-p", "base"]);
+        assert_eq!(response.status, 400);
+        assert!(body(&response).contains("inflates to more than"), "{}", body(&response));
+    }
+
+    #[test]
+    fn rejects_malformed_escape() {
+        let response = exchange(post("/print?width=%zz", &png(2, 2)), &["serve"]);
+        assert_eq!(response.status, 400);
+    }
+}
@@ -0,0 +1,45 @@
+//! Blueprint strings don't depend on the number of worker threads
+use image::{Rgba, RgbaImage};
+use factorio_printer::printer::{FactorioBPStringBuilder, ImagePrinter, TileOrder, Tileset};
+
+/// Gradient with a transparent corner, so some pixels get no tile
+fn picture() -> RgbaImage {
+    RgbaImage::from_fn(23, 17, |x, y| {
+        let alpha = if x + y < 6 { 0 } else { 255 };
+        Rgba::from([(x * 11) as u8, (y * 15) as u8, ((x + y) * 6) as u8, alpha])
+    })
+}
+
+/// Blueprint string made on a pool of `threads` workers
+fn print(threads: usize, split: i32, tile_order: TileOrder) -> String {
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+    pool.install(|| {
+        let tileset = Tileset::preset_color_coding().unwrap();
+        let (_, indexed) = ImagePrinter::new(&tileset).print(picture()).unwrap();
+        FactorioBPStringBuilder::new("golden", &indexed, &tileset)
+            .split(split)
+            .tile_order(tile_order)
+            .factorio_serialize()
+            .unwrap()
+    })
+}
+
+fn golden(name: &str) -> String {
+    std::fs::read_to_string(format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+}
+
+#[test]
+fn blueprint_matches_golden() {
+    let expected = golden("golden-blueprint.txt");
+    for threads in [1, 4] {
+        assert_eq!(print(threads, 0, TileOrder::Scan), expected.trim(), "{} threads", threads);
+    }
+}
+
+#[test]
+fn book_matches_golden() {
+    let expected = golden("golden-book.txt");
+    for threads in [1, 4] {
+        assert_eq!(print(threads, 8, TileOrder::Grouped), expected.trim(), "{} threads", threads);
+    }
+}
@@ -0,0 +1,44 @@
+//! Calibration pattern sampled back from a screenshot of itself
+use image::{Rgba, RgbaImage};
+use factorio_printer::printer::{CalibrationPattern, PrinterError, Tileset};
+
+/// Pattern as the game would show it, `scale` pixels per tile on black ground
+fn screenshot(tileset: &Tileset, pattern: &CalibrationPattern, scale: u32) -> RgbaImage {
+    let indexed = pattern.indexed();
+    let (width, height) = indexed.dimensions();
+    let mut tiles = RgbaImage::from_pixel(width, height, Rgba::from([0, 0, 0, 255]));
+    indexed.paint(&mut tiles, tileset);
+    image::imageops::resize(&tiles, width * scale, height * scale, image::imageops::Nearest)
+}
+
+#[test]
+fn sampled_colors_match_tileset() {
+    let tileset = Tileset::preset_base_game().unwrap();
+    let pattern = CalibrationPattern::new(&tileset).swatch(3);
+    let shot = screenshot(&tileset, &pattern, 5);
+    let (width, height) = pattern.dimensions();
+    let colors = pattern.sample(&shot, [0, 0, width * 5, height * 5]).unwrap();
+    let expected: Vec<_> = tileset.iter().map(|tile| tile.color(Default::default())).collect();
+    assert_eq!(colors, expected);
+}
+
+#[test]
+fn swatches_are_labelled_in_their_own_entry() {
+    let tileset = Tileset::preset_color_coding().unwrap();
+    let indexed = CalibrationPattern::new(&tileset).swatch(4).indexed();
+    // "1" under the first swatch: a row of gap, then 7 rows of the glyph
+    let label: Vec<_> = indexed.enumerate()
+        .filter(|(x, y, _)| *x < 5 && (5..12).contains(y))
+        .collect();
+    assert_eq!(label.len(), 10);
+    assert!(label.iter().all(|(_, _, index)| *index == 0));
+    assert!((0..4).all(|x| indexed.get(x, 4).is_none()));
+}
+
+#[test]
+fn box_off_the_screenshot_is_rejected() {
+    let tileset = Tileset::preset_color_coding().unwrap();
+    let shot = RgbaImage::new(64, 64);
+    let result = CalibrationPattern::new(&tileset).sample(&shot, [u32::MAX - 4, 0, 10, 10]);
+    assert!(matches!(result, Err(PrinterError::InvalidInput(_))));
+}
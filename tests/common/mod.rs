@@ -0,0 +1,24 @@
+//! Helpers shared by tests and benches, benches include this file with `#[path]`
+use std::io::Cursor;
+use factorio_printer::printer::Tileset;
+
+pub struct XorShift(pub u32);
+
+impl XorShift {
+    pub fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+}
+
+/// Large modded palette: random colors, `count` entries. Large enough for the k-d tree from 32 on
+pub fn synthetic_tileset(count: usize, rng: &mut XorShift) -> Tileset {
+    let mut csv = String::from("red,green,blue,name,is_tile\n");
+    for i in 0..count {
+        let c = rng.next();
+        csv += &format!("{},{},{},tile-{},true\n", c & 0xff, (c >> 8) & 0xff, (c >> 16) & 0xff, i);
+    }
+    Tileset::read(Box::new(Cursor::new(csv.into_bytes()))).expect("valid tileset")
+}
@@ -0,0 +1,57 @@
+//! Blueprint strings decoded and rendered back
+use image::{Pixel, Rgba, RgbaImage};
+use serde_json::json;
+use factorio_printer::printer::{factorio_deserialize, factorio_deserialize_within, factorio_encode, BlueprintRenderer, FactorioBPStringBuilder, ImagePrinter, PrinterError, Tileset};
+
+/// Gradient with transparent right and bottom edges, so the last pages are smaller than the split side
+fn picture() -> RgbaImage {
+    RgbaImage::from_fn(23, 17, |x, y| {
+        let alpha = if x >= 20 || y >= 15 { 0 } else { 255 };
+        Rgba::from([(x * 11) as u8, (y * 15) as u8, ((x + y) * 6) as u8, alpha])
+    })
+}
+
+#[test]
+fn split_book_renders_back_into_the_print() {
+    let tileset = Tileset::preset_color_coding().unwrap();
+    let (_, indexed) = ImagePrinter::new(&tileset).print(picture()).unwrap();
+    let data = FactorioBPStringBuilder::new("book", &indexed, &tileset)
+        .split(8)
+        .factorio_serialize()
+        .unwrap();
+    let blueprint = factorio_deserialize(&data).unwrap();
+    let (image, warnings) = BlueprintRenderer::new(&blueprint, &tileset).render().unwrap();
+    assert!(warnings.is_empty());
+    assert_eq!(image.dimensions(), (20, 15));
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let expected = indexed.get(x, y).map(|index| tileset.iter().nth(index).unwrap().color(Default::default()).to_rgba());
+        assert_eq!(Some(*pixel), expected, "pixel {},{}", x, y);
+    }
+}
+
+#[test]
+fn oversized_payload_is_rejected() {
+    let tiles: Vec<_> = (0..1000).map(|_| json!({"name": "concrete", "position": {"x": 0, "y": 0}})).collect();
+    let data = factorio_encode(&json!({"blueprint": {"item": "blueprint", "tiles": tiles, "version": 0}})).unwrap();
+    assert!(data.len() < 1000);
+    match factorio_deserialize_within(&data, 10_000) {
+        Err(PrinterError::BlueprintDecode { message, .. }) =>
+            assert_eq!(message, "blueprint inflates to more than 10000 bytes"),
+        other => panic!("expected decode error, got {:?}", other.map(|_| ())),
+    }
+    assert!(factorio_deserialize_within(&data, 100_000).is_ok());
+}
+
+#[test]
+fn render_checks_extent_before_drawing() {
+    let tileset = Tileset::preset_base_game().unwrap();
+    let data = factorio_encode(&json!({"blueprint": {"item": "blueprint", "tiles": [
+        {"name": "concrete", "position": {"x": 0, "y": 0}},
+        {"name": "concrete", "position": {"x": 2147483647, "y": -2147483648}}
+    ], "version": 0}})).unwrap();
+    let blueprint = factorio_deserialize(&data).unwrap();
+    match BlueprintRenderer::new(&blueprint, &tileset).max_pixels(100).render() {
+        Err(PrinterError::BlueprintDecode { message, .. }) => assert!(message.starts_with("blueprint spans "), "{}", message),
+        other => panic!("expected decode error, got {:?}", other.map(|_| ())),
+    }
+}
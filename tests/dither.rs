@@ -0,0 +1,42 @@
+//! Every dither mode against a checked-in preview of the same fixture picture.
+//! Run with `UPDATE_FIXTURES=1` to write new previews after an intended change
+use image::ImageFormat;
+use factorio_printer::printer::{load_image, DitherMode, ImagePrinter, Tileset};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+fn check_mode(name: &str, linear_light: bool) {
+    let tileset = Tileset::preset_color_coding().unwrap();
+    let (picture, _) = load_image(&std::fs::read(format!("{}/gradient.png", FIXTURES)).unwrap()).unwrap();
+    let (mut preview, indexed) = ImagePrinter::new(&tileset)
+        .dither(DitherMode::from_name(name).unwrap())
+        .linear_light(linear_light)
+        .print(picture)
+        .unwrap();
+    indexed.paint(&mut preview, &tileset);
+
+    let suffix = if linear_light { "-linear" } else { "" };
+    let path = format!("{}/dither/{}{}.png", FIXTURES, name, suffix);
+    if std::env::var_os("UPDATE_FIXTURES").is_some() {
+        preview.save_with_format(&path, ImageFormat::Png).unwrap();
+    }
+    let (expected, _) = load_image(&std::fs::read(&path).unwrap()).unwrap();
+    assert_eq!(preview.dimensions(), expected.dimensions(), "{}", path);
+    if let Some((x, y, _)) = preview.enumerate_pixels().find(|(x, y, pixel)| expected.get_pixel(*x, *y) != *pixel) {
+        panic!("{} differs at {},{}: {:?} instead of {:?}", path, x, y, preview.get_pixel(x, y), expected.get_pixel(x, y));
+    }
+}
+
+#[test]
+fn every_mode_matches_fixture() {
+    for name in DitherMode::names() {
+        check_mode(name, false);
+    }
+}
+
+#[test]
+fn linear_light_matches_fixture() {
+    for name in ["floyd-steinberg", "bayer4"] {
+        check_mode(name, true);
+    }
+}
@@ -0,0 +1,44 @@
+//! Tilesets built from a data-raw dump
+use std::io::Cursor;
+use serde_json::json;
+use factorio_printer::printer::{NameFilter, TileKind, Tileset};
+
+fn from_dump(dump: serde_json::Value) -> Tileset {
+    Tileset::from_dump(Box::new(Cursor::new(dump.to_string().into_bytes())), &NameFilter::new(), None).unwrap()
+}
+
+#[test]
+fn dump_entities_need_own_color_and_collision_box() {
+    let mut tileset = from_dump(json!({
+        "item": {
+            "concrete": {"place_as_tile": {"result": "concrete"}},
+            "wall": {"place_result": "stone-wall"},
+            "lamp": {"place_result": "small-lamp"},
+            "pole": {"place_result": "small-electric-pole"},
+            "furnace": {"place_result": "stone-furnace"}
+        },
+        "tile": {
+            "concrete": {"map_color": {"r": 0.25, "g": 0.25, "b": 0.25}}
+        },
+        "wall": {
+            "stone-wall": {"map_color": [1, 0.5, 0], "collision_box": [[-0.29, -0.29], [0.29, 0.29]]}
+        },
+        "lamp": {
+            "small-lamp": {"collision_box": [[-0.15, -0.15], [0.15, 0.15]]}
+        },
+        "electric-pole": {
+            "small-electric-pole": {"friendly_map_color": {"r": 0, "g": 0, "b": 1}}
+        },
+        "furnace": {
+            "stone-furnace": {"map_color": [0.5, 0.5, 0.5], "collision_box": [[-0.7, -0.7], [0.7, 0.7]]}
+        },
+        "utility-constants": {
+            "default": {"chart": {"default_friendly_color_by_type": {"lamp": [1, 1, 1]}}}
+        }
+    }));
+    let names: Vec<_> = tileset.iter().map(|tile| (tile.name(), tile.kind())).collect();
+    assert_eq!(names, [("concrete", TileKind::Tile), ("stone-wall", TileKind::Entity)]);
+    let warnings: Vec<_> = tileset.take_warnings().iter().map(|warning| warning.to_string()).collect();
+    assert_eq!(warnings, ["warning: entity 'small-electric-pole' has no collision_box, skipped"]);
+    assert!(tileset.take_warnings().is_empty());
+}
@@ -0,0 +1,56 @@
+//! Ground view colors from fixture sprites in a game data directory and a mod folder
+use std::io::Cursor;
+use image::Rgb;
+use serde_json::json;
+use factorio_printer::printer::{ColorView, GroundColor, GroundSprites, NameFilter, Tileset};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/ground");
+
+fn ground_tileset(mode: GroundColor) -> Tileset {
+    let dump = json!({
+        "item": {
+            "concrete": {"place_as_tile": {"result": "concrete"}},
+            "my-tile": {"place_as_tile": {"result": "my-tile"}}
+        },
+        "tile": {
+            "concrete": {
+                "map_color": {"r": 0.25, "g": 0.25, "b": 0.25},
+                "variants": {"material_background": {"picture": "__base__/graphics/terrain/concrete.png"}}
+            },
+            "my-tile": {
+                "map_color": {"r": 1, "g": 1, "b": 1},
+                "variants": {"main": [{"picture": "__my-tiles__/graphics/tile.png"}]}
+            }
+        }
+    });
+    let ground = GroundSprites::new(mode)
+        .root(format!("{}/data", FIXTURES))
+        .root(format!("{}/my-tiles", FIXTURES));
+    let src = Box::new(Cursor::new(dump.to_string().into_bytes()));
+    Tileset::from_dump(src, &NameFilter::new(), Some(&ground)).unwrap()
+}
+
+fn ground_color(tileset: &Tileset, name: &str) -> Option<Rgb<u8>> {
+    tileset.get(name).unwrap().profile(ColorView::Ground)
+}
+
+#[test]
+fn average_is_taken_in_linear_light() {
+    let tileset = ground_tileset(GroundColor::Average);
+    // 11 pixels of 100 and a crack of 20, transparent row left out
+    assert_eq!(ground_color(&tileset, "concrete"), Some(Rgb::from([96, 96, 96])));
+    // sprite from the mod folder, half red and half blue
+    assert_eq!(ground_color(&tileset, "my-tile"), Some(Rgb::from([146, 0, 146])));
+}
+
+#[test]
+fn median_ignores_cracks() {
+    let tileset = ground_tileset(GroundColor::Median);
+    assert_eq!(ground_color(&tileset, "concrete"), Some(Rgb::from([100, 100, 100])));
+}
+
+#[test]
+fn map_colors_are_kept() {
+    let tileset = ground_tileset(GroundColor::Average);
+    assert_eq!(tileset.get("concrete").unwrap().profile(ColorView::Map), Some(Rgb::from([64, 64, 64])));
+}
@@ -0,0 +1,47 @@
+//! Nearest tile lookup gives the same answer as exhaustive search
+use image::Rgb;
+use factorio_printer::printer::{ColorMetric, Tileset};
+
+mod common;
+use common::{synthetic_tileset, XorShift};
+
+const METRICS: [(&str, ColorMetric); 6] = [
+    ("rgb", ColorMetric::WeightedRgb([1.0, 1.0, 1.0])),
+    ("linear-rgb", ColorMetric::LinearRgb([1.0, 1.0, 1.0])),
+    ("redmean", ColorMetric::Redmean),
+    ("cie76", ColorMetric::Cie76),
+    ("ciede2000", ColorMetric::Ciede2000),
+    ("oklab", ColorMetric::Oklab),
+];
+
+/// Every 16th color of the cube plus random ones
+fn samples(rng: &mut XorShift) -> Vec<Rgb<u8>> {
+    let grid = (0..16u32 * 16 * 16).map(|i| Rgb::from([i & 15, (i >> 4) & 15, i >> 8].map(|c| (c * 16 + 8) as u8)));
+    let random = (0..4096).map(|_| {
+        let c = rng.next();
+        Rgb::from([c as u8, (c >> 8) as u8, (c >> 16) as u8])
+    });
+    grid.chain(random).collect()
+}
+
+#[test]
+fn lookup_matches_exhaustive_search() {
+    let mut rng = XorShift(0x1234_5678);
+    let samples = samples(&mut rng);
+    let tilesets = [
+        ("colorcoding", Tileset::preset_color_coding().unwrap()),
+        ("synthetic-256", synthetic_tileset(256, &mut rng)),
+    ];
+    for (tileset_name, mut tileset) in tilesets {
+        for (metric_name, metric) in METRICS {
+            tileset.set_metric(metric);
+            for color in &samples {
+                assert_eq!(
+                    tileset.nearest(color),
+                    tileset.nearest_exhaustive(color),
+                    "{} {} lookup differs from exhaustive search for {:?}", tileset_name, metric_name, color
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,15 @@
+//! Tileset preview legend
+use image::Rgb;
+use factorio_printer::printer::{Tile, TileKind, TilesetBuilder, TilesetPreview};
+
+fn preview_width(name: &str) -> u32 {
+    let tileset = TilesetBuilder::new()
+        .tile(Tile::new(name, TileKind::Tile, Rgb([10, 20, 30])))
+        .build().unwrap();
+    TilesetPreview::new(&tileset).scale(1).render().width()
+}
+
+#[test]
+fn columns_fit_non_ascii_names_by_glyph() {
+    assert_eq!(preview_width("béton"), preview_width("beton"));
+}
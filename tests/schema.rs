@@ -0,0 +1,64 @@
+//! Blueprint strings made in the game survive decode and encode
+use serde_json::{json, Value};
+use factorio_printer::printer::{factorio_deserialize, factorio_encode};
+
+/// Decodes `value` encoded as a blueprint string and encodes it back
+fn round_trip(value: &Value) -> Value {
+    let decoded = factorio_deserialize(&factorio_encode(value).unwrap()).unwrap();
+    let encoded = factorio_encode(&decoded).unwrap();
+    serde_json::to_value(factorio_deserialize(&encoded).unwrap()).unwrap()
+}
+
+#[test]
+fn blueprint_round_trip() {
+    let blueprint = json!({
+        "blueprint": {
+            "item": "blueprint",
+            "label": "Smelting",
+            "description": "copper and iron",
+            "icons": [
+                {"index": 1, "signal": {"type": "item", "name": "stone-furnace", "quality": "rare"}, "color": "red"}
+            ],
+            "entities": [
+                {
+                    "entity_number": 1,
+                    "name": "inserter",
+                    "position": {"x": 0.5, "y": 1.5, "z": 0},
+                    "direction": 4,
+                    "control_behavior": {"circuit_enabled": true},
+                    "connections": {"1": {"red": [{"entity_id": 2}]}}
+                },
+                {"entity_number": 2, "name": "small-lamp", "position": {"x": -3, "y": 2}}
+            ],
+            "tiles": [
+                {"name": "refined-concrete", "position": {"x": 0, "y": 0}, "layer": 2},
+                {"name": "stone-path", "position": {"x": -1, "y": 7}}
+            ],
+            "schedules": [{"locomotives": [1], "schedule": []}],
+            "snap-to-grid": {"x": 4, "y": 4},
+            "absolute-snapping": true,
+            "position-relative-to-grid": {"x": 1, "y": 2},
+            "version": 281479275675648u64
+        }
+    });
+    assert_eq!(round_trip(&blueprint), blueprint);
+}
+
+#[test]
+fn book_round_trip() {
+    let book = json!({
+        "blueprint_book": {
+            "item": "blueprint-book",
+            "label": "Everything",
+            "blueprints": [
+                {"index": 0, "blueprint": {"item": "blueprint", "tiles": [{"name": "concrete", "position": {"x": 1, "y": 1}}]}},
+                {"index": 1, "blueprint_book": {"item": "blueprint-book", "blueprints": [], "active_index": 0}},
+                {"index": 2, "upgrade_planner": {"item": "upgrade-planner", "settings": {"mappers": []}, "version": 1}},
+                {"index": 3, "deconstruction_planner": {"item": "deconstruction-planner", "settings": {"trees_and_rocks_only": true}}}
+            ],
+            "version": 281479275675648u64,
+            "unknown": [1, 2, 3]
+        }
+    });
+    assert_eq!(round_trip(&book), book);
+}
@@ -0,0 +1,82 @@
+//! Building and changing tilesets in code
+use std::io::Cursor;
+use image::Rgb;
+use factorio_printer::printer::{Direction, Tile, TileKind, Tileset, TilesetBuilder, TilesetError, TilesetFormat};
+
+fn tile(name: &str, color: [u8; 3]) -> Tile {
+    Tile::new(name, TileKind::Tile, Rgb::from(color))
+}
+
+#[test]
+fn builder_rejects_more_entries_than_indices() {
+    let builder = (0..=u16::MAX as u32 + 1).fold(TilesetBuilder::new(), |builder, i| {
+        builder.tile(tile(&format!("tile-{}", i), [0, 0, 0]))
+    });
+    assert_eq!(builder.build().err(), Some(TilesetError::TooLarge(65537)));
+}
+
+#[test]
+fn csv_lists_take_numeric_looking_items() {
+    let csv = "name,kind,color,aliases,groups,notes\n\
+        concrete,tile,#3a3a3a,1,2;3,4\n\
+        stone-path,tile,#505050,,,\n";
+    let tileset = Tileset::read(Box::new(Cursor::new(csv))).unwrap();
+    let concrete = tileset.get("1").unwrap();
+    assert_eq!(concrete.name(), "concrete");
+    assert_eq!(concrete.groups(), ["2", "3"]);
+    assert_eq!(concrete.notes(), Some("4"));
+    assert!(tileset.get("stone-path").unwrap().aliases().is_empty());
+}
+
+#[test]
+fn json_lists_are_arrays() {
+    let json = r##"[{"name": "concrete", "kind": "tile", "color": "#3a3a3a", "aliases": ["1", "old"], "directions": "north;south"}]"##;
+    let tileset = Tileset::read_as(Box::new(Cursor::new(json)), Some(TilesetFormat::Json), None).unwrap();
+    let concrete = tileset.get("old").unwrap();
+    assert_eq!(concrete.aliases(), ["1", "old"]);
+    assert_eq!(concrete.directions(), [Direction::North, Direction::South]);
+}
+
+#[test]
+fn colors_take_hex_digits_only() {
+    for color in ["#+1+2+3", "#-1a2b3", "#3a3a3g"] {
+        let csv = format!("name,kind,color\nconcrete,tile,{}\n", color);
+        assert!(Tileset::read(Box::new(Cursor::new(csv))).is_err(), "{} was accepted", color);
+    }
+}
+
+fn aliased(name: &str, aliases: &[&str], color: [u8; 3]) -> Tile {
+    let mut tile = tile(name, color);
+    tile.set_aliases(aliases.iter().map(|alias| alias.to_string()).collect());
+    tile
+}
+
+#[test]
+fn merge_replaces_entries_matched_by_alias() {
+    let mut tileset = TilesetBuilder::new()
+        .tile(aliased("concrete", &["old-concrete"], [60, 60, 60]))
+        .tile(tile("stone-path", [80, 80, 70]))
+        .build().unwrap();
+    let layer = TilesetBuilder::new()
+        .tile(aliased("new-concrete", &["concrete"], [10, 10, 10]))
+        .tile(tile("landfill", [50, 40, 30]))
+        .build().unwrap();
+    tileset.merge(layer).unwrap();
+    let names: Vec<&str> = tileset.iter().map(|tile| tile.name()).collect();
+    assert_eq!(names, ["new-concrete", "stone-path", "landfill"]);
+    assert_eq!(tileset.get("concrete").unwrap().color(Default::default()), Rgb([10, 10, 10]));
+}
+
+#[test]
+fn merge_rejects_entry_matching_several() {
+    let mut tileset = TilesetBuilder::new()
+        .tile(tile("concrete", [60, 60, 60]))
+        .tile(tile("stone-path", [80, 80, 70]))
+        .build().unwrap();
+    let layer = TilesetBuilder::new()
+        .tile(aliased("concrete", &["stone-path"], [10, 10, 10]))
+        .build().unwrap();
+    assert_eq!(tileset.merge(layer).err(), Some(TilesetError::DuplicateName("stone-path".to_string())));
+    assert_eq!(tileset.len(), 2);
+    assert_eq!(tileset.get("concrete").unwrap().color(Default::default()), Rgb([60, 60, 60]));
+}